@@ -0,0 +1,71 @@
+// parserとmake_packetの処理時間を測る
+// cargo run --release --example bench
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use serial_packet::{make_packet, make_packet_with, parser, parser_with, Framing, LengthWidth};
+
+
+/// 1回あたりの平均時間を表示する．
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+    // キャッシュ等を温める
+    for _ in 0..(iterations / 10) {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed();
+    println!("{:<24} {:>10.1} ns/iter", name, elapsed.as_nanos() as f64 / iterations as f64);
+}
+
+fn main() {
+    const ITERATIONS: u32 = 1_000_000;
+
+    for len in [1, 16, 127] {
+        let data: Vec<u8> = (0..len).map(|n| n as u8).collect();
+        // make_packetはデータを取り出すので，コピーする時間も含む
+        bench(&format!("make_packet ({} bytes)", len), ITERATIONS, || {
+            black_box(make_packet(&mut black_box(data.clone())).unwrap());
+        });
+
+        let packet = make_packet(&mut data.clone()).unwrap();
+        bench(&format!("parser ({} bytes)", len), ITERATIONS, || {
+            black_box(parser(black_box(&packet), 0).unwrap());
+        });
+
+        // 前に64Byteのゴミがある場合
+        let mut buf = vec![0x00; 64];
+        buf.extend(&packet);
+        bench(&format!("parser ({} bytes, +64)", len), ITERATIONS, || {
+            black_box(parser(black_box(&buf), 0).unwrap());
+        });
+    }
+
+    // 256Byteのメインデータ（4Byteのデータサイズ部）で，メインデータのコピー方法を比べる．
+    // 以前のparserは1Byteずつpushしていた．
+    let framing = Framing { length_width: LengthWidth::Bits32, ..Framing::default() };
+    let data: Vec<u8> = (0..256).map(|n| n as u8).collect();
+    let packet = make_packet_with(&mut data.clone(), &framing).unwrap();
+    let payload = &packet[7..(7 + 256)];
+    assert_eq!(payload, &data[..]);
+    bench("copy by push (256 bytes)", ITERATIONS, || {
+        let payload = black_box(payload);
+        let mut main_data: Vec<u8> = Vec::with_capacity(payload.len());
+        for &byte in payload {
+            main_data.push(byte);
+        }
+        black_box(main_data);
+    });
+    bench("copy by extend (256 bytes)", ITERATIONS, || {
+        let payload = black_box(payload);
+        let mut main_data: Vec<u8> = Vec::with_capacity(payload.len());
+        main_data.extend_from_slice(payload);
+        black_box(main_data);
+    });
+    bench("parser_with (256 bytes)", ITERATIONS, || {
+        black_box(parser_with(black_box(&packet), 0, &framing).unwrap());
+    });
+}
@@ -0,0 +1,190 @@
+// チェックサムの種類と計算
+//
+// 不明な相手の形式を調べる場合は，正常に受信できたパケットのメインデータとチェックサムを
+// いくつか集めてdetect_checksumに渡すと，全てに一致する種類を返す．
+
+use crate::Endian;
+
+
+/// チェックサムの種類
+/// CRCの各パラメータはCRC RevEngのカタログに合わせている．
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumKind {
+    /// 各バイトのXOR（1Byte）
+    Xor,
+    /// 各バイトの和の下位8bit（1Byte）
+    Sum,
+    /// CRC-8/SMBUS（poly 0x07, init 0x00）
+    Crc8,
+    /// CRC-8/MAXIM（poly 0x31, init 0x00, 反転あり）
+    Crc8Maxim,
+    /// CRC-16/CCITT-FALSE（poly 0x1021, init 0xFFFF）．Endianは送信するバイト順．
+    Crc16CcittFalse(Endian),
+    /// CRC-16/XMODEM（poly 0x1021, init 0x0000）
+    Crc16Xmodem(Endian),
+    /// CRC-16/MODBUS（poly 0x8005, init 0xFFFF, 反転あり）
+    Crc16Modbus(Endian),
+    /// CRC-16/ARC（poly 0x8005, init 0x0000, 反転あり）
+    Crc16Arc(Endian),
+}
+
+/// detect_checksumで試す種類（この順に試す）
+pub(crate) const CANDIDATES: [ChecksumKind; 12] = [
+    ChecksumKind::Xor,
+    ChecksumKind::Sum,
+    ChecksumKind::Crc8,
+    ChecksumKind::Crc8Maxim,
+    ChecksumKind::Crc16CcittFalse(Endian::Big),
+    ChecksumKind::Crc16CcittFalse(Endian::Little),
+    ChecksumKind::Crc16Xmodem(Endian::Big),
+    ChecksumKind::Crc16Xmodem(Endian::Little),
+    ChecksumKind::Crc16Modbus(Endian::Big),
+    ChecksumKind::Crc16Modbus(Endian::Little),
+    ChecksumKind::Crc16Arc(Endian::Big),
+    ChecksumKind::Crc16Arc(Endian::Little),
+];
+
+impl ChecksumKind {
+    /// チェックサムのByte数
+    pub fn width(&self) -> usize {
+        match self {
+            ChecksumKind::Xor | ChecksumKind::Sum | ChecksumKind::Crc8 | ChecksumKind::Crc8Maxim => 1,
+            _ => 2,
+        }
+    }
+
+    /// CRCの生成多項式．CRC以外はNone．
+    pub fn polynomial(&self) -> Option<u16> {
+        match self {
+            ChecksumKind::Xor | ChecksumKind::Sum => None,
+            ChecksumKind::Crc8 => Some(0x07),
+            ChecksumKind::Crc8Maxim => Some(0x31),
+            ChecksumKind::Crc16CcittFalse(_) | ChecksumKind::Crc16Xmodem(_) => Some(0x1021),
+            ChecksumKind::Crc16Modbus(_) | ChecksumKind::Crc16Arc(_) => Some(0x8005),
+        }
+    }
+
+    /// チェックサムの値を計算する．
+    pub fn compute(&self, data: &[u8]) -> u16 {
+        self.compute_bytes(data.iter().copied())
+    }
+
+    /// 複数の範囲にまたがるデータのチェックサムを計算する．
+    pub(crate) fn compute_bytes<I: Iterator<Item = u8>>(&self, data: I) -> u16 {
+        match self {
+            ChecksumKind::Xor => data.fold(0, |num, byte| num ^ byte) as u16,
+            ChecksumKind::Sum => data.fold(0u8, |num, byte| num.wrapping_add(byte)) as u16,
+            ChecksumKind::Crc8 => crc(data, 8, 0x07, 0x00, false),
+            ChecksumKind::Crc8Maxim => crc(data, 8, 0x31, 0x00, true),
+            ChecksumKind::Crc16CcittFalse(_) => crc(data, 16, 0x1021, 0xFFFF, false),
+            ChecksumKind::Crc16Xmodem(_) => crc(data, 16, 0x1021, 0x0000, false),
+            ChecksumKind::Crc16Modbus(_) => crc(data, 16, 0x8005, 0xFFFF, true),
+            ChecksumKind::Crc16Arc(_) => crc(data, 16, 0x8005, 0x0000, true),
+        }
+    }
+
+    /// チェックサムの値を送信するバイト列にする．先頭のwidth() Byteだけを使う．
+    pub(crate) fn value_bytes(&self, value: u16) -> [u8; 2] {
+        match self {
+            ChecksumKind::Crc16CcittFalse(endian)
+            | ChecksumKind::Crc16Xmodem(endian)
+            | ChecksumKind::Crc16Modbus(endian)
+            | ChecksumKind::Crc16Arc(endian) => match endian {
+                Endian::Big    => value.to_be_bytes(),
+                Endian::Little => value.to_le_bytes(),
+            },
+            _ => [value as u8, 0],
+        }
+    }
+
+    /// チェックサムを計算し，送信するバイト列としてpacketの末尾に追加する．
+    pub fn write(&self, data: &[u8], packet: &mut Vec<u8>) {
+        let bytes = self.value_bytes(self.compute(data));
+        packet.extend_from_slice(&bytes[..self.width()]);
+    }
+
+    /// 受信したチェックサムcheckがdataから計算した値と一致すればtrue
+    pub fn verify(&self, data: &[u8], check: &[u8]) -> bool {
+        let bytes = self.value_bytes(self.compute(data));
+        &bytes[..self.width()] == check
+    }
+}
+
+/// 正常に受信できたパケットの(メインデータ, チェックサム)の組から，チェックサムの種類を推定する．
+/// 全ての組に一致する最初の種類を返す．samplesが空の場合や，一致する種類が無い場合はNone．
+/// 1Byteの種類はサンプルが少ないと偶然一致しやすいので，なるべく多くのパケットを渡すこと．
+pub fn detect_checksum(samples: &[(&[u8], &[u8])]) -> Option<ChecksumKind> {
+    if samples.is_empty() {
+        return None;
+    }
+    CANDIDATES.iter().copied().find(|kind| {
+        samples.iter().all(|&(data, check)| kind.verify(data, check))
+    })
+}
+
+/// 1bitずつ計算するCRC（width: 8か16）
+/// reflect: 入力と出力のビット順を反転する．
+fn crc<I: Iterator<Item = u8>>(data: I, width: u32, poly: u16, init: u16, reflect: bool) -> u16 {
+    let mask = if width == 16 { 0xFFFF } else { (1u16 << width) - 1 };
+    let mut crc = init;
+    if reflect {
+        let poly = poly.reverse_bits() >> (16 - width);
+        for byte in data {
+            crc ^= byte as u16;
+            for _ in 0..8 {
+                crc = if (crc & 1) != 0 { (crc >> 1) ^ poly } else { crc >> 1 };
+            }
+        }
+    } else {
+        let top = 1u16 << (width - 1);
+        for byte in data {
+            crc ^= (byte as u16) << (width - 8);
+            for _ in 0..8 {
+                crc = if (crc & top) != 0 { (crc << 1) ^ poly } else { crc << 1 };
+            }
+            crc &= mask;
+        }
+    }
+    crc & mask
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_values() {
+        // 各カタログの"123456789"に対する値
+        let data = b"123456789";
+        assert_eq!(ChecksumKind::Crc8.compute(data), 0xF4);
+        assert_eq!(ChecksumKind::Crc8Maxim.compute(data), 0xA1);
+        assert_eq!(ChecksumKind::Crc16CcittFalse(Endian::Big).compute(data), 0x29B1);
+        assert_eq!(ChecksumKind::Crc16Xmodem(Endian::Big).compute(data), 0x31C3);
+        assert_eq!(ChecksumKind::Crc16Modbus(Endian::Big).compute(data), 0x4B37);
+        assert_eq!(ChecksumKind::Crc16Arc(Endian::Big).compute(data), 0xBB3D);
+    }
+
+    #[test]
+    fn test_detect_checksum() {
+        // このクレートのパケット（XOR）
+        let samples: [(&[u8], &[u8]); 2] = [(&[0x01, 0x23, 0xAB, 0xCD], &[0x44]), (&[0x10], &[0x10])];
+        assert_eq!(detect_checksum(&samples), Some(ChecksumKind::Xor));
+
+        // Modbus RTU（下位バイトから送る）
+        let payloads: [&[u8]; 3] = [&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A], b"123456789", &[0xFF]];
+        let checks: Vec<Vec<u8>> = payloads.iter().map(|data| {
+            let mut check = Vec::new();
+            ChecksumKind::Crc16Modbus(Endian::Little).write(data, &mut check);
+            check
+        }).collect();
+        assert_eq!(checks[0], vec![0xC5, 0xCD]);
+        let samples: Vec<(&[u8], &[u8])> = payloads.iter().copied().zip(checks.iter().map(|c| &c[..])).collect();
+        let kind = detect_checksum(&samples).unwrap();
+        assert_eq!(kind, ChecksumKind::Crc16Modbus(Endian::Little));
+        assert_eq!(kind.polynomial(), Some(0x8005));
+
+        assert_eq!(detect_checksum(&[(&[0x01, 0x02], &[0x00, 0x00, 0x00])]), None);
+        assert_eq!(detect_checksum(&[]), None);
+    }
+}
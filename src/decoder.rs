@@ -0,0 +1,397 @@
+// 受信データを1Byteずつ入力してパケットを復元するストリーミングデコーダ
+//
+// 内部状態（どこまで読んだか，受信途中のメインデータ）は全て構造体の中に持つので，
+// cloneすればその時点の状態を複製して別々に読み進めることができる．
+// 中断シーケンスを設定すると，受信中のパケットを捨ててヘッダの探索に戻り，push_eventでAbortを通知する．
+
+
+/// デコーダの状態．次に読むべきフィールドを表す．
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header1,
+    Header2,
+    SizeH,
+    SizeL,
+    Const,
+    Data,
+    Checksum,
+    Footer,
+}
+
+/// push_eventで通知する出来事
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecoderEvent {
+    /// パケットを最後まで読めた（メインデータ）
+    Frame(Vec<u8>),
+    /// 中断シーケンスを受信した．受信途中のデータは捨てた．
+    Abort,
+}
+
+/// ストリーミングデコーダ
+#[derive(Debug, Clone)]
+pub struct PacketDecoder {
+    state: State,
+    data_size: usize,
+    checksum: u8,
+    main_data: Vec<u8>,
+    /// これより長く受信が途切れたらパケットの途中でもヘッダの探索に戻る（push_timedでのみ使う）
+    gap_threshold: Option<u64>,
+    /// 最後にpush_timedで受信した時刻
+    last_timestamp: Option<u64>,
+    /// 中断シーケンス（空の場合は使わない）
+    abort_sequence: Vec<u8>,
+    /// 中断シーケンスと比較するための直近の受信データ
+    recent: Vec<u8>,
+}
+
+impl Default for PacketDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacketDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Header1,
+            data_size: 0,
+            checksum: 0,
+            main_data: Vec::new(),
+            gap_threshold: None,
+            last_timestamp: None,
+            abort_sequence: Vec::new(),
+            recent: Vec::new(),
+        }
+    }
+
+    /// 受信した1Byteを入力する．
+    /// パケットの終端まで読んで整合性が取れた場合にメインデータを返す．
+    /// 途中で形式が崩れていた場合はそれまでのデータを捨ててヘッダの探索に戻る．
+    pub fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match self.push_event(byte) {
+            Some(DecoderEvent::Frame(main_data)) => Some(main_data),
+            _ => None,
+        }
+    }
+
+    /// 中断シーケンスを設定する．Noneの場合は使わない（デフォルト）．
+    /// 中断シーケンスを受信すると，パケットの途中でもそれまでのデータを捨ててヘッダの探索に戻る．
+    pub fn set_abort_sequence(&mut self, sequence: Option<&[u8]>) {
+        self.abort_sequence = sequence.map(|sequence| sequence.to_vec()).unwrap_or_default();
+        self.recent.clear();
+    }
+
+    /// pushと同じだが，中断シーケンスの受信もAbortとして返す．
+    pub fn push_event(&mut self, byte: u8) -> Option<DecoderEvent> {
+        if !self.abort_sequence.is_empty() {
+            if self.recent.len() == self.abort_sequence.len() {
+                self.recent.remove(0);
+            }
+            self.recent.push(byte);
+            if self.recent == self.abort_sequence {
+                self.recent.clear();
+                self.main_data.clear();
+                self.state = State::Header1;
+                return Some(DecoderEvent::Abort);
+            }
+        }
+        self.step(byte).map(DecoderEvent::Frame)
+    }
+
+    /// 状態に応じて1Byteを読む．
+    fn step(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match self.state {
+            State::Header1 => {
+                if byte == 0xA5 {
+                    self.state = State::Header2;
+                }
+            },
+            State::Header2 => {
+                if byte == 0x5A {
+                    self.state = State::SizeH;
+                } else if byte != 0xA5 {
+                    self.state = State::Header1;
+                }
+            },
+            State::SizeH => {
+                if (byte & 0x80) == 0x80 {
+                    self.data_size = ((byte & 0x7F) as usize) << 8;
+                    self.state = State::SizeL;
+                } else {
+                    self.reset(byte);
+                }
+            },
+            State::SizeL => {
+                self.data_size |= byte as usize;
+                if self.data_size == 0 {
+                    self.reset(byte);
+                } else {
+                    self.state = State::Const;
+                }
+            },
+            State::Const => {
+                if byte == 0xA0 {
+                    self.main_data.clear();
+                    self.main_data.reserve_exact(self.data_size);
+                    self.checksum = 0;
+                    self.state = State::Data;
+                } else {
+                    self.reset(byte);
+                }
+            },
+            State::Data => {
+                self.main_data.push(byte);
+                self.checksum ^= byte;
+                if self.main_data.len() == self.data_size {
+                    self.state = State::Checksum;
+                }
+            },
+            State::Checksum => {
+                if (self.checksum ^ byte) == 0 {
+                    self.state = State::Footer;
+                } else {
+                    self.reset(byte);
+                }
+            },
+            State::Footer => {
+                if byte == 0x04 {
+                    self.state = State::Header1;
+                    return Some(std::mem::take(&mut self.main_data));
+                }
+                self.reset(byte);
+            },
+        }
+        None
+    }
+
+    /// 受信間隔の閾値を設定する．Noneの場合は受信間隔を見ない（デフォルト）．
+    /// 単位はpush_timedに渡す時刻と同じ．
+    pub fn set_gap_threshold(&mut self, threshold: Option<u64>) {
+        self.gap_threshold = threshold;
+    }
+
+    /// 受信した1Byteを受信時刻と共に入力する．
+    /// 前回の受信から閾値より長く途切れていた場合は，パケットの途中でもそれまでのデータを捨て，
+    /// このバイトを新しいパケットの先頭として読む．
+    /// パケットの間に無通信の期間がある回線で，バイトの欠落による同期ずれから早く復帰するために使う．
+    /// 時刻は単調増加でなければならない（単位は任意）．
+    pub fn push_timed(&mut self, byte: u8, timestamp: u64) -> Option<Vec<u8>> {
+        if let (Some(threshold), Some(last)) = (self.gap_threshold, self.last_timestamp) {
+            if timestamp.saturating_sub(last) > threshold && self.is_in_frame() {
+                self.main_data.clear();
+                self.state = State::Header1;
+            }
+        }
+        self.last_timestamp = Some(timestamp);
+        self.push(byte)
+    }
+
+    /// 受信したバイト列をまとめて入力する．
+    /// バイト列の中で完成した全てのパケットのメインデータを順に返す．
+    /// 末尾で途切れたパケットは内部に保持し，次回の入力で続きを読む．
+    pub fn decode_chunk(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        for &byte in bytes {
+            if let Some(main_data) = self.push(byte) {
+                frames.push(main_data);
+            }
+        }
+        frames
+    }
+
+    /// パケットの途中であればtrue
+    pub fn is_in_frame(&self) -> bool {
+        self.state != State::Header1
+    }
+
+    /// 受信途中のデータを捨ててヘッダの探索に戻る．
+    fn reset(&mut self, byte: u8) {
+        self.main_data.clear();
+        // 形式が崩れた原因のバイトが次のパケットの先頭である可能性がある
+        self.state = if byte == 0xA5 { State::Header2 } else { State::Header1 };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::make_packet;
+
+    #[test]
+    fn test_clone_mid_frame() {
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+
+        let mut decoder = PacketDecoder::new();
+        for &byte in &packet[..7] {
+            assert_eq!(decoder.push(byte), None);
+        }
+        let mut fork = decoder.clone();
+
+        // 複製側には壊れたデータを入力する
+        for &byte in &[0xAB, 0xCD, 0x00, 0x04] {
+            assert_eq!(fork.push(byte), None);
+        }
+        assert!(!fork.is_in_frame());
+
+        // 元のデコーダは複製側の影響を受けない
+        let mut result = None;
+        for &byte in &packet[7..] {
+            result = decoder.push(byte);
+        }
+        assert_eq!(result, Some(vec![0x01, 0x23, 0xAB, 0xCD]));
+
+        // 複製側も続けて別のパケットを読める
+        let mut result = None;
+        for &byte in &packet {
+            result = fork.push(byte);
+        }
+        assert_eq!(result, Some(vec![0x01, 0x23, 0xAB, 0xCD]));
+    }
+
+    #[test]
+    fn test_decode_chunk() {
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        let mut stream: Vec<u8> = vec![0x45, 0x22];
+        stream.extend_from_slice(&packet);
+        stream.extend_from_slice(&packet);
+        stream.extend_from_slice(&packet[..5]);
+
+        // 1回の入力に2つのパケットと途切れたパケットを含む
+        let mut decoder = PacketDecoder::new();
+        let frames = decoder.decode_chunk(&stream);
+        assert_eq!(frames, vec![vec![0x01, 0x23, 0xAB, 0xCD]; 2]);
+        assert!(decoder.is_in_frame());
+
+        // 途切れたパケットの続きを入力する
+        let frames = decoder.decode_chunk(&packet[5..]);
+        assert_eq!(frames, vec![vec![0x01, 0x23, 0xAB, 0xCD]]);
+        assert!(decoder.decode_chunk(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_abort_sequence() {
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        let abort: [u8; 3] = [0x18, 0x18, 0x18];
+        let mut stream: Vec<u8> = packet[..7].to_vec();
+        stream.extend(&abort);
+        stream.extend(&packet);
+
+        let mut decoder = PacketDecoder::new();
+        decoder.set_abort_sequence(Some(&abort));
+        let mut events = Vec::new();
+        for (i, &byte) in stream.iter().enumerate() {
+            if let Some(event) = decoder.push_event(byte) {
+                events.push(event);
+            }
+            // 中断シーケンスの直後はヘッダの探索に戻っている
+            if i == 9 {
+                assert!(!decoder.is_in_frame());
+            }
+        }
+        assert_eq!(events, vec![DecoderEvent::Abort, DecoderEvent::Frame(vec![0x01, 0x23, 0xAB, 0xCD])]);
+
+        // 設定しなければ中断シーケンスもメインデータとして読む（チェックサムが一致せずに捨てる）
+        let mut decoder = PacketDecoder::new();
+        let events: Vec<_> = stream.iter().filter_map(|&byte| decoder.push_event(byte)).collect();
+        assert_eq!(events, vec![DecoderEvent::Frame(vec![0x01, 0x23, 0xAB, 0xCD])]);
+    }
+
+    #[test]
+    fn test_gap_resync() {
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        let mut stream: Vec<(u8, u64)> = Vec::new();
+        // 途中のバイトが欠けたパケット（無通信の期間を挟んで次のパケットが届く）
+        stream.extend(packet[..7].iter().enumerate().map(|(i, &byte)| (byte, i as u64)));
+        stream.extend(packet.iter().enumerate().map(|(i, &byte)| (byte, 100 + i as u64)));
+
+        // 受信間隔を見ない場合は，次のパケットのヘッダをメインデータとして読んでしまう
+        let mut decoder = PacketDecoder::new();
+        let frames: Vec<_> = stream.iter().filter_map(|&(byte, t)| decoder.push_timed(byte, t)).collect();
+        assert!(frames.is_empty());
+
+        let mut decoder = PacketDecoder::new();
+        decoder.set_gap_threshold(Some(10));
+        let frames: Vec<_> = stream.iter().filter_map(|&(byte, t)| decoder.push_timed(byte, t)).collect();
+        assert_eq!(frames, vec![vec![0x01, 0x23, 0xAB, 0xCD]]);
+
+        // ストリームの途中でヘッダの1Byte目（または2Byte目）が欠けたパケット．
+        // メインデータにヘッダと同じ並びがあるので，受信間隔を見ない場合はそこから長いパケットとして読んでしまう
+        let first = make_packet(&mut vec![0x01]).unwrap();
+        let second = make_packet(&mut vec![0xA5, 0x5A, 0x80, 0x20, 0xA0, 0x11]).unwrap();
+        let third = make_packet(&mut vec![0x02, 0x03]).unwrap();
+        for dropped in 0..2 {
+            let mut lossy = second.clone();
+            lossy.remove(dropped);
+            let mut stream: Vec<(u8, u64)> = Vec::new();
+            for (n, frame) in [&first, &lossy, &third].iter().enumerate() {
+                stream.extend(frame.iter().enumerate().map(|(i, &byte)| (byte, 100 * n as u64 + i as u64)));
+            }
+
+            let mut decoder = PacketDecoder::new();
+            let frames: Vec<_> = stream.iter().filter_map(|&(byte, t)| decoder.push_timed(byte, t)).collect();
+            assert_eq!(frames, vec![vec![0x01]]);
+
+            let mut decoder = PacketDecoder::new();
+            decoder.set_gap_threshold(Some(10));
+            let frames: Vec<_> = stream.iter().filter_map(|&(byte, t)| decoder.push_timed(byte, t)).collect();
+            assert_eq!(frames, vec![vec![0x01], vec![0x02, 0x03]]);
+        }
+    }
+
+    /// 長時間の連続受信でデータが欠けたりメモリが増え続けたりしないことを確認する．
+    /// 時間がかかるので通常は実行しない（cargo test --release -- --ignored）．
+    #[test]
+    #[ignore]
+    fn soak_test() {
+        const FRAMES: usize = 2_000_000;
+
+        // xorshift
+        let mut seed: u32 = 0x1234_5678;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        let mut decoder = PacketDecoder::new();
+        let mut expected: Vec<Vec<u8>> = Vec::new();
+        let mut stream: Vec<u8> = Vec::new();
+        let mut decoded = 0;
+        for n in 0..FRAMES {
+            let len = (rand() % 0x7F) as usize + 1;
+            let data: Vec<u8> = (0..len).map(|_| rand() as u8).collect();
+            let mut packet = make_packet(&mut data.clone()).unwrap();
+
+            match rand() % 16 {
+                // 壊れたパケット（チェックサムが一致しない）
+                0 => packet[5] ^= 0x55,
+                // パケットの間のゴミ（ヘッダの先頭と同じ値は含まない）
+                1 => {
+                    stream.extend((0..(rand() % 8)).map(|_| (rand() as u8) & 0x7F));
+                    expected.push(data);
+                },
+                _ => expected.push(data),
+            }
+            stream.extend_from_slice(&packet);
+
+            // ランダムな長さに区切って入力する
+            if stream.len() > 4096 || n == FRAMES - 1 {
+                let mut rest = &stream[..];
+                while !rest.is_empty() {
+                    let chunk = ((rand() % 512) as usize + 1).min(rest.len());
+                    for main_data in decoder.decode_chunk(&rest[..chunk]) {
+                        assert_eq!(main_data, expected[decoded]);
+                        decoded += 1;
+                    }
+                    rest = &rest[chunk..];
+                    assert!(decoder.main_data.capacity() <= 0x7F);
+                }
+                stream.clear();
+            }
+        }
+        assert_eq!(decoded, expected.len());
+        assert!(!decoder.is_in_frame());
+    }
+}
@@ -0,0 +1,276 @@
+// パケットの解析過程を人が読める形で出力する診断用の関数
+//
+// suggest_formatは，ヘッダは見つかるが解析できないパケットについて，
+// 相手の形式がデフォルトのFramingとどう違うかを推定する．
+
+use std::fmt::Write;
+
+use crate::checksum::CANDIDATES;
+use crate::{find_header, parse_prefix_traced, parser, parser_with, skip_delimiters, ChecksumCoverage, ChecksumKind, Endian, FieldTrace, Framing, LengthLimits, LengthPosition, LengthWidth, PacketError, Terminator};
+
+
+/// デフォルトのFramingから変更する設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatChange {
+    LengthWidth(LengthWidth),
+    LengthEndian(Endian),
+    LengthPosition(LengthPosition),
+    ChecksumKind(ChecksumKind),
+    ChecksumCoverage(ChecksumCoverage),
+}
+
+/// パケットを解析できるようになる設定の変更
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatHint {
+    /// デフォルトのFramingから変更する設定
+    pub changes: Vec<FormatChange>,
+    /// 変更を反映したFraming
+    pub framing: Framing,
+}
+
+
+/// バッファ内の最初のパケットを解析し，各フィールドをどう読んだかを1行ずつ説明した文字列を返す．
+/// デフォルトのFramingで解析する．最後の行はparserの結果．
+pub fn explain(buf: &[u8]) -> String {
+    explain_with(buf, &Framing::default())
+}
+
+/// 形式を指定してexplainと同じ説明を返す．最後の行はparser_withの結果．
+/// 各フィールドの説明はparser_withが実際に読んだ位置から作る．
+/// データサイズ部が後ろにある形式とエスケープする形式では，フィールドの説明は省く．
+pub fn explain_with(buf: &[u8], framing: &Framing) -> String {
+    let mut report = String::new();
+    let result = explain_fields(buf, framing, &mut report);
+    match result {
+        Ok((main_data_len, head_pos, tail_pos)) => {
+            writeln!(report, "result: ok ({} bytes of main data, bytes {}..={})", main_data_len, head_pos, tail_pos).unwrap();
+        },
+        Err(e) => writeln!(report, "result: {}", e).unwrap(),
+    }
+    report
+}
+
+/// 各フィールドの説明を書き込み，解析の結果を返す．
+/// return: (メインデータのByte数, head_pos, tail_pos)
+fn explain_fields(buf: &[u8], framing: &Framing, report: &mut String) -> Result<(usize, usize, usize), PacketError> {
+    framing.validate()?;
+    let head_pos = match find_header(buf, 0, framing.header) {
+        Ok(head_pos) => skip_delimiters(buf, head_pos, framing),
+        Err(e) => {
+            writeln!(report, "header: not found in {} bytes", buf.len()).unwrap();
+            return Err(e);
+        },
+    };
+    writeln!(report, "header: found at {} ({} bytes skipped)", head_pos, head_pos).unwrap();
+
+    if framing.length_position != LengthPosition::Prefix || framing.escape.is_some() {
+        writeln!(report, "fields: not traced for this layout").unwrap();
+        return parser_with(buf, 0, framing).map(|(main_data, head_pos, tail_pos)| (main_data.len(), head_pos, tail_pos));
+    }
+
+    let mut trace = FieldTrace::default();
+    let result = parse_prefix_traced(buf, head_pos + framing.header.len(), framing, false, LengthLimits::default(), &mut trace);
+
+    // 固定値は，4Byteのデータサイズ部ではその前に，2Byteでは後ろにある
+    if framing.length_width == LengthWidth::Bits32 {
+        explain_constant(buf, framing, &trace, report);
+    }
+    if let Some((pos, declared, data_size)) = trace.length {
+        let field = hex(&buf[pos..(pos + framing.length_width.size())]);
+        if declared == data_size {
+            writeln!(report, "length: {} at {} -> {} bytes", field, pos, data_size).unwrap();
+        } else {
+            writeln!(report, "length: {} at {} -> {} bytes (declared {})", field, pos, data_size, declared).unwrap();
+        }
+    }
+    if framing.length_width == LengthWidth::Bits15 {
+        explain_constant(buf, framing, &trace, report);
+    }
+    if let Some((start, end)) = trace.payload {
+        writeln!(report, "payload: bytes {}..{}", start, end).unwrap();
+    }
+    if let Some((pos, computed)) = trace.checksum {
+        let width = framing.checksum_kind.width();
+        let received = &buf[pos..(pos + width)];
+        let matched = if received == &computed[..width] { "ok" } else { "mismatch" };
+        writeln!(report, "checksum: computed {}, received {} at {} ({})", hex(&computed[..width]), hex(received), pos, matched).unwrap();
+    }
+    if let (Some(pos), Terminator::Footer) = (trace.footer, framing.terminator) {
+        let matched = if buf[pos] == framing.footer { "ok" } else { "mismatch" };
+        writeln!(report, "footer: {:02X} at {} (expected {:02X}, {})", buf[pos], pos, framing.footer, matched).unwrap();
+    }
+
+    result.map(|(main_data, _, tail_pos)| (main_data.len(), head_pos, tail_pos))
+}
+
+/// 固定値の説明を書き込む．
+fn explain_constant(buf: &[u8], framing: &Framing, trace: &FieldTrace, report: &mut String) {
+    if let Some(pos) = trace.constant {
+        let matched = if buf[pos] == framing.constant { "ok" } else { "mismatch" };
+        writeln!(report, "const: {:02X} at {} (expected {:02X}, {})", buf[pos], pos, framing.constant, matched).unwrap();
+    }
+}
+
+/// バイト列を空白区切りの16進数にする．
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+/// ヘッダは見つかるがデフォルトのFramingでは解析できないバッファについて，
+/// データサイズ部の幅・バイト順・位置とチェックサムの種類・範囲を変えて解析し直し，
+/// 解析できるようになる変更の組を返す（変更の少ない順）．
+/// ある組で解析できる場合，それを含むより大きな組は返さない．
+/// ヘッダが無い場合や，デフォルトのFramingで解析できる場合は空．
+/// 1Byteのチェックサムは偶然一致することがあるので，複数のパケットで確かめること．
+pub fn suggest_format(buf: &[u8]) -> Vec<FormatHint> {
+    let default = Framing::default();
+    if find_header(buf, 0, default.header).is_err() || parser(buf, 0).is_ok() {
+        return Vec::new();
+    }
+
+    let mut hints: Vec<FormatHint> = Vec::new();
+    for width in [LengthWidth::Bits15, LengthWidth::Bits32] {
+        // バイト順は4Byteのデータサイズ部でのみ意味がある
+        let endians: &[Endian] = if width == LengthWidth::Bits32 { &[Endian::Big, Endian::Little] } else { &[Endian::Big] };
+        for &endian in endians {
+            for position in [LengthPosition::Prefix, LengthPosition::Suffix] {
+                for coverage in [ChecksumCoverage::PayloadOnly, ChecksumCoverage::LengthAndPayload] {
+                    for kind in CANDIDATES {
+                        let framing = Framing {
+                            length_width: width,
+                            length_endian: endian,
+                            length_position: position,
+                            checksum_coverage: coverage,
+                            checksum_kind: kind,
+                            ..default
+                        };
+                        if parser_with(buf, 0, &framing).is_ok() {
+                            hints.push(FormatHint { changes: changes_from(&default, &framing), framing });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    hints.sort_by_key(|hint| hint.changes.len());
+    let mut minimal: Vec<FormatHint> = Vec::new();
+    for hint in hints {
+        if !minimal.iter().any(|m| m.changes.iter().all(|change| hint.changes.contains(change))) {
+            minimal.push(hint);
+        }
+    }
+    minimal
+}
+
+/// baseからframingへの変更を列挙する．
+fn changes_from(base: &Framing, framing: &Framing) -> Vec<FormatChange> {
+    let mut changes = Vec::new();
+    if framing.length_width != base.length_width {
+        changes.push(FormatChange::LengthWidth(framing.length_width));
+    }
+    if framing.length_endian != base.length_endian {
+        changes.push(FormatChange::LengthEndian(framing.length_endian));
+    }
+    if framing.length_position != base.length_position {
+        changes.push(FormatChange::LengthPosition(framing.length_position));
+    }
+    if framing.checksum_kind != base.checksum_kind {
+        changes.push(FormatChange::ChecksumKind(framing.checksum_kind));
+    }
+    if framing.checksum_coverage != base.checksum_coverage {
+        changes.push(FormatChange::ChecksumCoverage(framing.checksum_coverage));
+    }
+    changes
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        let packet: Vec<u8> = vec![0x45, 0x22, 0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        assert_eq!(explain(&packet), "\
+            header: found at 2 (2 bytes skipped)\n\
+            length: 80 04 at 4 -> 4 bytes\n\
+            const: A0 at 6 (expected A0, ok)\n\
+            payload: bytes 7..11\n\
+            checksum: computed 44, received 44 at 11 (ok)\n\
+            footer: 04 at 12 (expected 04, ok)\n\
+            result: ok (4 bytes of main data, bytes 2..=12)\n");
+
+        let mut broken = packet.clone();
+        broken[11] = 0x45;
+        let report = explain(&broken);
+        assert!(report.contains("checksum: computed 44, received 45 at 11 (mismatch)\n"));
+        assert!(!report.contains("footer:"));
+        assert!(report.ends_with("result: Checksum mismatch at byte 11.\n"));
+    }
+
+    #[test]
+    fn test_explain_with() {
+        use crate::make_packet_with;
+
+        // 形式の設定に従って読む
+        let framing = Framing {
+            length_width: LengthWidth::Bits32,
+            length_offset: 2,
+            checksum_kind: ChecksumKind::Crc16CcittFalse(Endian::Big),
+            ..Framing::default()
+        };
+        let packet = make_packet_with(&mut b"123456789".to_vec(), &framing).unwrap();
+        assert_eq!(explain_with(&packet, &framing), "\
+            header: found at 0 (0 bytes skipped)\n\
+            const: A0 at 2 (expected A0, ok)\n\
+            length: 00 00 00 0B at 3 -> 9 bytes (declared 11)\n\
+            payload: bytes 7..16\n\
+            checksum: computed 29 B1, received 29 B1 at 16 (ok)\n\
+            footer: 04 at 18 (expected 04, ok)\n\
+            result: ok (9 bytes of main data, bytes 0..=18)\n");
+
+        // 結果の行はparser_withと一致する
+        for len in 0..packet.len() {
+            let report = explain_with(&packet[..len], &framing);
+            let expected = match parser_with(&packet[..len], 0, &framing) {
+                Ok(_) => unreachable!(),
+                Err(e) => format!("result: {}\n", e),
+            };
+            assert!(report.ends_with(&expected), "{}", report);
+        }
+        let mut broken = packet.clone();
+        broken[17] ^= 0x01;
+        let report = explain_with(&broken, &framing);
+        assert!(report.contains("checksum: computed 29 B1, received 29 B0 at 16 (mismatch)\n"));
+        assert!(report.ends_with(&format!("result: {}\n", parser_with(&broken, 0, &framing).unwrap_err())));
+    }
+
+    #[test]
+    fn test_suggest_format() {
+        use crate::make_packet_with;
+
+        // 4Byteのデータサイズ部（下位バイトから）とCRC-8の相手
+        let framing = Framing { length_width: LengthWidth::Bits32, length_endian: Endian::Little, checksum_kind: ChecksumKind::Crc8, ..Framing::default() };
+        let packet = make_packet_with(&mut b"123456789".to_vec(), &framing).unwrap();
+        assert!(parser(&packet, 0).is_err());
+        let hints = suggest_format(&packet);
+        assert_eq!(hints[0].changes, vec![
+            FormatChange::LengthWidth(LengthWidth::Bits32),
+            FormatChange::LengthEndian(Endian::Little),
+            FormatChange::ChecksumKind(ChecksumKind::Crc8),
+        ]);
+        assert_eq!(hints[0].framing, framing);
+
+        // チェックサムの種類だけが異なる
+        let framing = Framing { checksum_kind: ChecksumKind::Crc16Modbus(Endian::Little), ..Framing::default() };
+        let packet = make_packet_with(&mut b"123456789".to_vec(), &framing).unwrap();
+        let hints = suggest_format(&packet);
+        assert_eq!(hints[0].changes, vec![FormatChange::ChecksumKind(ChecksumKind::Crc16Modbus(Endian::Little))]);
+
+        // 解析できる，ヘッダが無い
+        let packet = make_packet_with(&mut b"123456789".to_vec(), &Framing::default()).unwrap();
+        assert!(suggest_format(&packet).is_empty());
+        assert!(suggest_format(&packet[2..]).is_empty());
+    }
+}
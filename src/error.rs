@@ -0,0 +1,150 @@
+// パケットの生成・解析で発生するエラー
+
+use std::fmt;
+
+
+/// 解析時のエラーの優先順位
+/// 1つのパケットに複数の誤りがある場合は，次の規則で1つだけを返す．
+/// 1. ヘッダが見つからなければHeaderNotFound
+/// 2. ヘッダ以降は前から順にフィールドを確認し，最初に誤りが見つかったものを返す．
+///    InvalidLengthMarker / ZeroLength / NoMainData / DeclaredExceedsMax / LengthMismatch（データサイズ部），InvalidConstByte（固定値），
+///    ChecksumMismatch（チェックサム），FooterMissing（フッタ）の順になる
+///    （LengthWidth::Bits32では固定値がデータサイズ部より前）．
+/// 3. 読めた範囲に誤りが無いままバッファが途切れていればIncompleteかTruncatedBody
+///
+/// そのため，形式の誤り（同期ずれを示す）はチェックサムの誤り（ノイズを示す）より先に報告される．
+/// エスケープを使う場合は，エスケープを元に戻したパケットに対してこの規則を適用する．
+///
+/// 形式の誤りには，原因となったバイトの位置（解析したバッファ内のindex）posが入る．
+/// エスケープを使う場合も，エスケープされたままのバッファ内の位置を返す．
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketError {
+    /// 送信するメインデータのサイズが0
+    EmptyData,
+    /// 送信するメインデータのサイズがパケットで送れる最大値を超えている
+    /// （Framing::pad_toを指定した場合はパケットがpad_toのサイズに，encode_into_sliceでは出力先に収まらない）
+    DataTooLong,
+    /// offsetがバッファの長さ以上（patch_payloadでは，書き換える範囲がメインデータを超えている）
+    OffsetOutOfRange,
+    /// ヘッダが見つからない
+    HeaderNotFound,
+    /// バッファ内でパケットが途切れている．
+    /// needed: パケットを最後まで読むのに不足しているByte数．
+    ///         データサイズ部を読む前に途切れている場合はNone．
+    Incomplete { needed: Option<usize> },
+    /// データサイズ部は読めたが，その後ろがバッファ内で途切れている．続きのデータを待てば読める．
+    /// declared: データサイズ部の値（Framing::length_offsetを引く前），
+    /// available: バッファ内にあるパケットのByte数（ヘッダから），
+    /// needed: パケットを最後まで読むのに不足しているByte数
+    TruncatedBody { declared: usize, available: usize, needed: usize },
+    /// データサイズ部の値が上限（送信し得る最大値かParseOptions::max_len）を超えている．
+    /// 続きを待っても読めないので，ヘッダの次から探索し直す．
+    DeclaredExceedsMax { declared: usize, max: usize },
+    /// データサイズ部のMSBが1でない
+    InvalidLengthMarker { pos: usize },
+    /// データサイズ部の値が0で，後ろのバイトもメインデータの無いパケットとして整合しない．
+    /// データサイズ部が壊れている可能性が高いので，ヘッダの次から探索し直す（posはデータサイズ部の先頭）．
+    NoMainData { pos: usize },
+    /// データサイズ部の値が0で，固定値・チェックサム・フッタがメインデータの無いパケットとして整合する．
+    /// キープアライブ等の意図的なパケットの可能性がある（posはデータサイズ部の先頭）．
+    ZeroLength { pos: usize },
+    /// 固定値が一致しない
+    InvalidConstByte { pos: usize },
+    /// チェックサムが一致しない
+    ChecksumMismatch { pos: usize },
+    /// フッタが無い（posはフッタがあるべき位置）
+    FooterMissing { pos: usize },
+    /// エスケープバイトの後ろにデータが無い
+    InvalidEscape { pos: usize },
+    /// メインデータのサイズが固定長のサイズ（FixedFrame，parse_fixed）と一致しない．
+    /// expected: 固定長のサイズ，actual: 実際のサイズ（受信時はデータサイズ部の値）
+    LengthMismatch { expected: usize, actual: usize },
+    /// エスケープしない設定で，送信するメインデータにフッタと同じ値がある（Framing::reject_footer_in_payload）．
+    /// LengthPosition::Suffixでエスケープしない場合は，メインデータの途中に整合する終端の候補があり，
+    /// 受信側で短いパケットとして読まれてしまう場合にも返す．
+    FooterByteInPayload,
+    /// Framingの設定が不適切（理由を含む）
+    InvalidFraming(&'static str),
+    /// 16進数のログに16進数として読めないトークンがある．
+    /// line: トークンがある行番号（1始まり）
+    MalformedHexToken { line: usize },
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketError::EmptyData => write!(f, "The main data size is 0."),
+            PacketError::DataTooLong => write!(f, "The data size exceeds the maximum value that can be sent in this packet."),
+            PacketError::OffsetOutOfRange => write!(f, "Packet size shorter than offset position."),
+            PacketError::HeaderNotFound => write!(f, "Header does not exist."),
+            PacketError::Incomplete { needed: Some(n) } => write!(f, "Packet is truncated ({} more bytes needed).", n),
+            PacketError::Incomplete { needed: None } => write!(f, "Packet is truncated before the data size part."),
+            PacketError::TruncatedBody { declared, available, needed } => write!(f, "Packet is truncated ({} bytes present, {} more bytes needed for data size {}).", available, needed, declared),
+            PacketError::DeclaredExceedsMax { declared, max } => write!(f, "The data size part declares {} bytes, exceeding the maximum {}.", declared, max),
+            PacketError::InvalidLengthMarker { pos } => write!(f, "Syntax error (The data size part MSB is not 1) at byte {}.", pos),
+            PacketError::NoMainData { pos } => write!(f, "Main data part is None (data size part at byte {}).", pos),
+            PacketError::ZeroLength { pos } => write!(f, "Well-formed packet without main data (data size part at byte {}).", pos),
+            PacketError::InvalidConstByte { pos } => write!(f, "Syntax error (The const byte does not match) at byte {}.", pos),
+            PacketError::ChecksumMismatch { pos } => write!(f, "Checksum mismatch at byte {}.", pos),
+            PacketError::FooterMissing { pos } => write!(f, "Footer does not exist at byte {}.", pos),
+            PacketError::InvalidEscape { pos } => write!(f, "Escape byte is not followed by an escaped byte at byte {}.", pos),
+            PacketError::LengthMismatch { expected, actual } => write!(f, "The main data size is {} but {} was expected.", actual, expected),
+            PacketError::FooterByteInPayload => write!(f, "The main data contains the footer byte."),
+            PacketError::InvalidFraming(reason) => write!(f, "Invalid framing: {}", reason),
+            PacketError::MalformedHexToken { line } => write!(f, "Malformed hex token at line {}.", line),
+        }
+    }
+}
+
+impl PacketError {
+    /// 原因となったバイトの位置．位置を持たないエラーはNone．
+    pub fn position(&self) -> Option<usize> {
+        match *self {
+            PacketError::InvalidLengthMarker { pos }
+            | PacketError::NoMainData { pos }
+            | PacketError::ZeroLength { pos }
+            | PacketError::InvalidConstByte { pos }
+            | PacketError::ChecksumMismatch { pos }
+            | PacketError::FooterMissing { pos }
+            | PacketError::InvalidEscape { pos } => Some(pos),
+            _ => None,
+        }
+    }
+
+    /// パケットを最後まで読むのに不足しているByte数．IncompleteかTruncatedBodyで分かる場合のみSome．
+    pub fn needed(&self) -> Option<usize> {
+        match *self {
+            PacketError::Incomplete { needed } => needed,
+            PacketError::TruncatedBody { needed, .. } => Some(needed),
+            _ => None,
+        }
+    }
+
+    /// バッファが途切れているだけで，続きのデータを待てば読める可能性がある場合はtrue
+    /// （IncompleteかTruncatedBody）．
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, PacketError::Incomplete { .. } | PacketError::TruncatedBody { .. })
+    }
+
+    /// 位置にbaseを足す．
+    /// 解析したバッファの先頭がストリーム全体のbase Byte目である場合に，ストリーム内の位置に変換する．
+    pub fn with_base(self, base: usize) -> Self {
+        self.map_position(|pos| pos + base)
+    }
+
+    /// 位置を変換する．
+    pub(crate) fn map_position<F: FnOnce(usize) -> usize>(self, f: F) -> Self {
+        match self {
+            PacketError::InvalidLengthMarker { pos } => PacketError::InvalidLengthMarker { pos: f(pos) },
+            PacketError::NoMainData { pos } => PacketError::NoMainData { pos: f(pos) },
+            PacketError::ZeroLength { pos } => PacketError::ZeroLength { pos: f(pos) },
+            PacketError::InvalidConstByte { pos } => PacketError::InvalidConstByte { pos: f(pos) },
+            PacketError::ChecksumMismatch { pos } => PacketError::ChecksumMismatch { pos: f(pos) },
+            PacketError::FooterMissing { pos } => PacketError::FooterMissing { pos: f(pos) },
+            PacketError::InvalidEscape { pos } => PacketError::InvalidEscape { pos: f(pos) },
+            e => e,
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
@@ -0,0 +1,106 @@
+// 長さが決まっているパケットをヒープを使わずに扱う固定長フレーム
+//
+// 形式はデフォルトのFraming（ヘッダ2Byte，データサイズ部2Byte，固定値，チェックサム，フッタ）に限る．
+// パケット全体の長さNからメインデータのサイズが決まるので，Nが形式に合わない場合はコンパイル時にエラーになる．
+
+use crate::{calc_checksum, PacketError};
+
+
+/// メインデータ以外のByte数（ヘッダ2Byte，データサイズ部2Byte，固定値，チェックサム，フッタ）
+const OVERHEAD: usize = 7;
+
+/// パケット全体をそのままスタック上に持つ固定長フレーム
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedFrame<const N: usize> {
+    bytes: [u8; N],
+}
+
+impl<const N: usize> FixedFrame<N> {
+    /// メインデータのサイズ
+    pub const PAYLOAD_LEN: usize = N - OVERHEAD;
+
+    /// メインデータからパケットを生成する．
+    /// メインデータのサイズはPAYLOAD_LENでなければならない．
+    pub fn encode(data: &[u8]) -> Result<Self, PacketError> {
+        const { assert!(N > OVERHEAD && N - OVERHEAD <= 0x7F, "N does not match the packet layout.") };
+        if data.len() != Self::PAYLOAD_LEN {
+            return Err(PacketError::LengthMismatch { expected: Self::PAYLOAD_LEN, actual: data.len() });
+        }
+
+        let mut bytes = [0u8; N];
+        bytes[0] = 0xA5;
+        bytes[1] = 0x5A;
+        bytes[2] = 0x80 | (data.len() >> 8) as u8;
+        bytes[3] = (0xFF & data.len()) as u8;
+        bytes[4] = 0xA0;
+        bytes[5..(N - 2)].copy_from_slice(data);
+        bytes[N - 2] = calc_checksum(data);
+        bytes[N - 1] = 0x04;
+        Ok(Self { bytes })
+    }
+
+    /// 受信したパケットを検査して取り込む．
+    /// エラーの優先順位はparserと同じ（前から順にフィールドを確認する）．
+    pub fn from_bytes(bytes: &[u8; N]) -> Result<Self, PacketError> {
+        const { assert!(N > OVERHEAD && N - OVERHEAD <= 0x7F, "N does not match the packet layout.") };
+        if bytes[0] != 0xA5 || bytes[1] != 0x5A {
+            return Err(PacketError::HeaderNotFound);
+        }
+        if (bytes[2] & 0x80) != 0x80 {
+            return Err(PacketError::InvalidLengthMarker { pos: 2 });
+        }
+        let data_size = (((bytes[2] & 0x7F) as usize) << 8) | bytes[3] as usize;
+        if data_size == 0 {
+            return Err(PacketError::NoMainData { pos: 2 });
+        } else if data_size != Self::PAYLOAD_LEN {
+            return Err(PacketError::LengthMismatch { expected: Self::PAYLOAD_LEN, actual: data_size });
+        }
+        if bytes[4] != 0xA0 {
+            return Err(PacketError::InvalidConstByte { pos: 4 });
+        }
+        if calc_checksum(&bytes[5..(N - 2)]) != bytes[N - 2] {
+            return Err(PacketError::ChecksumMismatch { pos: N - 2 });
+        }
+        if bytes[N - 1] != 0x04 {
+            return Err(PacketError::FooterMissing { pos: N - 1 });
+        }
+        Ok(Self { bytes: *bytes })
+    }
+
+    /// メインデータ
+    pub fn payload(&self) -> &[u8] {
+        &self.bytes[5..(N - 2)]
+    }
+
+    /// パケット全体
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.bytes
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{make_packet, parser};
+
+    #[test]
+    fn test_fixed_frame() {
+        let frame = FixedFrame::<11>::encode(&[0x01, 0x23, 0xAB, 0xCD]).unwrap();
+        assert_eq!(FixedFrame::<11>::PAYLOAD_LEN, 4);
+        assert_eq!(&frame.as_bytes()[..], &make_packet(&mut vec![0x01, 0x23, 0xAB, 0xCD]).unwrap()[..]);
+        assert_eq!(parser(frame.as_bytes(), 0).unwrap().0, frame.payload());
+
+        let decoded = FixedFrame::from_bytes(frame.as_bytes()).unwrap();
+        assert_eq!(decoded, frame);
+        assert_eq!(decoded.payload(), &[0x01, 0x23, 0xAB, 0xCD]);
+
+        assert_eq!(FixedFrame::<11>::encode(&[0x01]), Err(PacketError::LengthMismatch { expected: 4, actual: 1 }));
+        let mut broken = *frame.as_bytes();
+        broken[9] ^= 0x01;
+        assert_eq!(FixedFrame::from_bytes(&broken), Err(PacketError::ChecksumMismatch { pos: 9 }));
+        let mut broken = *frame.as_bytes();
+        broken[3] = 0x03;
+        assert_eq!(FixedFrame::from_bytes(&broken), Err(PacketError::LengthMismatch { expected: 4, actual: 3 }));
+    }
+}
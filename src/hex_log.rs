@@ -0,0 +1,87 @@
+// テキスト形式の通信ログ（1トークン1Byteの16進数）からパケットを取り出す
+
+use crate::{frames, PacketError};
+
+
+/// 16進数のログを解析してパケットを取り出す．
+/// トークンは空白またはカンマで区切り，"A5"，"a5"，"0xA5"の形式を受け付ける．
+/// return: ログ内の全てのパケットの解析結果を順に並べたもの．
+///         16進数として読めないトークンは読み飛ばし，その行番号（1始まり）を
+///         MalformedHexTokenとして先頭に並べる．
+pub fn parse_from_hex_log(text: &str) -> Vec<Result<Vec<u8>, PacketError>> {
+    parse_from_hex_log_with(text, None)
+}
+
+/// タイムスタンプ等の行頭の文字列を除いて16進数のログを解析する．
+/// prefix_delim: Someの場合，各行で最初に現れるこの文字までを読み飛ばす．
+///               この文字を含まない行はそのまま解析する．
+///               例えば "12:00:01.123> A5 5A 80" のようなログでは Some('>') とする．
+pub fn parse_from_hex_log_with(text: &str, prefix_delim: Option<char>) -> Vec<Result<Vec<u8>, PacketError>> {
+    let mut results = Vec::new();
+    let mut stream: Vec<u8> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = match prefix_delim.and_then(|delim| line.find(delim).map(|pos| (pos, delim))) {
+            Some((pos, delim)) => &line[(pos + delim.len_utf8())..],
+            None => line,
+        };
+        for token in line.split(|c: char| c.is_whitespace() || c == ',').filter(|t| !t.is_empty()) {
+            match parse_hex_token(token) {
+                Some(byte) => stream.push(byte),
+                None => results.push(Err(PacketError::MalformedHexToken { line: n + 1 })),
+            }
+        }
+    }
+
+    results.extend(frames(&stream));
+    results
+}
+
+/// 1Byte分の16進数を読む．
+fn parse_hex_token(token: &str) -> Option<u8> {
+    let digits = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")).unwrap_or(token);
+    if digits.is_empty() || digits.len() > 2 {
+        return None;
+    }
+    u8::from_str_radix(digits, 16).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_from_hex_log() {
+        let log = "\
+            45 22 A5 5A 80 04\n\
+            a0 01 23 ab cd 44 04 zz\n\
+            0xA5, 0x5A, 0x80, 0x01, 0xA0, 0x10, 0x11, 0x04\n\
+            A5 5A 80 01 A0 10 10 04 123\n";
+        let results = parse_from_hex_log(log);
+        assert_eq!(results, vec![
+            Err(PacketError::MalformedHexToken { line: 2 }),
+            Err(PacketError::MalformedHexToken { line: 4 }),
+            Ok(vec![0x01, 0x23, 0xAB, 0xCD]),
+            Err(PacketError::ChecksumMismatch { pos: 19 }),
+            Ok(vec![0x10]),
+        ]);
+
+        // 行頭のタイムスタンプを読み飛ばす
+        let log = "\
+            12:00:01.100> A5 5A 80 04 A0\n\
+            12:00:01.104> 01 23 AB CD 44 04\n";
+        assert_eq!(parse_from_hex_log_with(log, Some('>')), vec![Ok(vec![0x01, 0x23, 0xAB, 0xCD])]);
+
+        // 長さを読み違えるノイズの後ろのパケットも取り出す
+        let log = "\
+            A5 5A 80 20 A0\n\
+            A5 5A 80 02 A0 01 23 22 04\n\
+            A5 5A 80 01 A0 45 45 04\n";
+        assert_eq!(parse_from_hex_log(log), vec![
+            Err(PacketError::TruncatedBody { declared: 32, available: 22, needed: 17 }),
+            Ok(vec![0x01, 0x23]),
+            Ok(vec![0x45]),
+        ]);
+    }
+}
@@ -0,0 +1,169 @@
+// バッファ内の複数のパケットを順に取り出すイテレータ
+
+use crate::{find_header, parse_prefix, parser_with, Framing, LengthLimits, PacketError};
+
+
+/// バッファ内のパケットを前から順に取り出す．
+/// 正常に読めたパケットの次はその終端の次から，壊れたパケットの次は
+/// そのヘッダの次のバイトから探索を続ける．
+/// バッファの終端で途切れたパケットはIncompleteを1回返して終わる．
+/// 途切れたパケットの後ろに別のヘッダがある場合は，壊れたパケットと同様に扱う．
+/// 正常に読めたパケットの中身は探索し直さないので，メインデータにヘッダと同じ並び
+/// （パケットそのものを含む）があっても別のパケットとして取り出すことはない．
+/// ヘッダとフッタが同じ区切りバイトの形式では，パケットのフッタから次のパケットを読む．
+#[derive(Debug, Clone)]
+pub struct PacketIter<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    framing: Framing,
+}
+
+impl<'a> PacketIter<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self::with_framing(buf, Framing::default())
+    }
+
+    /// 形式を指定する．
+    pub fn with_framing(buf: &'a [u8], framing: Framing) -> Self {
+        Self { buf, pos: 0, framing }
+    }
+
+    /// 次に探索を始める位置
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// 次に探索を始める位置をposまで進める．
+    fn advance(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+}
+
+impl<'a> Iterator for PacketIter<'a> {
+    type Item = Result<Vec<u8>, PacketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head_pos = match find_header(self.buf, self.pos, self.framing.header) {
+            Ok(head_pos) => head_pos,
+            Err(_) => {
+                self.advance(self.buf.len());
+                return None;
+            },
+        };
+
+        match parser_with(self.buf, head_pos, &self.framing) {
+            Ok((main_data, _, tail_pos)) => {
+                // 次はこのパケットの終端の直後（区切りバイトを兼ねる場合は終端）から読む．
+                // そこがヘッダでなければ前方に探索する．
+                // バッファ末尾の区切りバイトは，次のパケットの先頭としては扱わない
+                let shared = self.framing.shares_delimiter() && tail_pos + 1 < self.buf.len();
+                self.advance(if shared { tail_pos } else { tail_pos + 1 });
+                Some(Ok(main_data))
+            },
+            Err(e) if e.is_retryable() => {
+                // 後ろに別のヘッダがあれば，長さを読み違えたノイズとしてその次のバイトから探索を続ける
+                let last = find_header(self.buf, head_pos + 1, self.framing.header).is_err();
+                self.advance(if last { self.buf.len() } else { head_pos + 1 });
+                Some(Err(e))
+            },
+            Err(e) => {
+                self.advance(head_pos + 1);
+                Some(Err(e))
+            },
+        }
+    }
+
+    /// 上限は残りのバッファに入る最小のパケットの数（Vecの容量を決める目安）．
+    /// 壊れたパケットや途切れたパケットはヘッダ毎に1つ返すので，これより多く返すことがある．
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.buf.len().saturating_sub(self.pos);
+        (0, Some(remaining / self.framing.min_frame_len()))
+    }
+}
+
+/// バッファ内の全てのパケットを取り出すイテレータを返す．
+pub fn frames(buf: &[u8]) -> PacketIter<'_> {
+    PacketIter::new(buf)
+}
+
+/// frames(buf)で正常に読めるパケットの数を返す．メインデータはコピーしない．
+/// 取り出す前に結果を入れるVecの容量を決める場合に使う．
+pub fn count_frames(buf: &[u8]) -> usize {
+    let framing = Framing::default();
+    let mut count = 0;
+    let mut pos = 0;
+    while let Ok(head_pos) = find_header(buf, pos, framing.header) {
+        match parse_prefix(buf, head_pos + framing.header.len(), &framing, false, LengthLimits::default()) {
+            Ok((_, _, tail_pos)) => {
+                count += 1;
+                pos = tail_pos + 1;
+            },
+            Err(_) => pos = head_pos + 1,
+        }
+    }
+    count
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::make_packet;
+
+    #[test]
+    fn test_frames() {
+        let mut buf: Vec<u8> = vec![0x45, 0x22];
+        buf.extend(make_packet(&mut vec![0x01, 0x23]).unwrap());
+        let mut broken = make_packet(&mut vec![0x10]).unwrap();
+        broken[5] ^= 0x01;
+        buf.extend(broken);
+        buf.extend(make_packet(&mut vec![0xAB, 0xCD]).unwrap());
+        buf.extend(&[0xA5, 0x5A, 0x80]);
+
+        let results: Vec<_> = frames(&buf).collect();
+        assert_eq!(results, vec![
+            Ok(vec![0x01, 0x23]),
+            Err(PacketError::ChecksumMismatch { pos: 17 }),
+            Ok(vec![0xAB, 0xCD]),
+            Err(PacketError::Incomplete { needed: None }),
+        ]);
+
+        // 上限は残りのByte数を最小のパケットのByte数（8Byte）で割ったもの
+        assert_eq!(frames(&buf).size_hint(), (0, Some(3)));
+        assert_eq!(count_frames(&buf), 2);
+        let mut iter = frames(&buf);
+        iter.next();
+        assert_eq!(iter.size_hint(), (0, Some(2)));
+        iter.by_ref().count();
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+    }
+
+    #[test]
+    fn test_spurious_long_header() {
+        // 長さの大きいノイズのヘッダの後ろに正常なパケットが続く
+        let mut buf: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x20, 0xA0];
+        buf.extend(make_packet(&mut vec![0x01, 0x23]).unwrap());
+        buf.extend(make_packet(&mut vec![0x45]).unwrap());
+
+        let results: Vec<_> = frames(&buf).collect();
+        assert_eq!(results, vec![
+            Err(PacketError::TruncatedBody { declared: 32, available: 22, needed: 17 }),
+            Ok(vec![0x01, 0x23]),
+            Ok(vec![0x45]),
+        ]);
+        assert_eq!(count_frames(&buf), 2);
+    }
+
+    #[test]
+    fn test_nested_frame() {
+        // メインデータの中にパケットが丸ごと入っている
+        let inner = make_packet(&mut vec![0x77]).unwrap();
+        let mut buf = make_packet(&mut inner.clone()).unwrap();
+        buf.extend(make_packet(&mut vec![0xA5, 0x5A, 0x80]).unwrap());
+        buf.extend(&[0x00]);
+        buf.extend(make_packet(&mut vec![0x01]).unwrap());
+
+        let results: Vec<_> = frames(&buf).collect();
+        assert_eq!(results, vec![Ok(inner), Ok(vec![0xA5, 0x5A, 0x80]), Ok(vec![0x01])]);
+    }
+}
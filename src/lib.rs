@@ -7,38 +7,589 @@
 // data size: main dataのByte数． MSBは1にする
 // checksum : main dataの全てのバイトのXORをとった値．全てのデータを正常に転送できた場合，
 //            受信側では，main dataの全てのバイトとchecksumのXORをとった結果が0になる．
+//
+// LengthPosition::Suffixを指定した場合は，data sizeをmain dataの後ろに置く．
+// [0xA5, 0x5A, 0xA0,  0x01, 0x23, 0xAB, 0xCD,  0x80, 0x04,   0x44  , 0x04]
+//    header  , const,      main data        ,  data size, checksum, footer
+//
+// LengthWidth::Bits32を指定した場合は，data sizeを4Byteとし，MSBの目印は付けない．
+// Prefixの場合はconstの直後に置く．
+// [0xA5, 0x5A, 0xA0,  0x00, 0x00, 0x00, 0x04,  0x01, 0x23, 0xAB, 0xCD,   0x44  , 0x04]
+//    header  , const,        data size      ,       main data       , checksum, footer
+//
+// unsafeは使わない．バッファへのアクセスは全て境界を確認するので，壊れたデータで未定義動作を起こすことはない．
+
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+
+mod checksum;
+mod decoder;
+mod diagnostics;
+mod error;
+mod fixed;
+mod hex_log;
+mod iter;
+#[cfg(feature = "async")]
+mod poll_stream;
+mod retransmit;
+mod sequence;
+mod stats;
+mod stream;
+mod transport;
+
+pub use checksum::{detect_checksum, ChecksumKind};
+pub use decoder::{DecoderEvent, PacketDecoder};
+pub use diagnostics::{explain, explain_with, suggest_format, FormatChange, FormatHint};
+pub use error::PacketError;
+pub use fixed::FixedFrame;
+pub use hex_log::{parse_from_hex_log, parse_from_hex_log_with};
+pub use iter::{count_frames, frames, PacketIter};
+#[cfg(feature = "async")]
+pub use poll_stream::FrameStream;
+pub use retransmit::RetransmitQueue;
+pub use sequence::{seq_cmp, reorder};
+pub use stats::DecoderStats;
+pub use stream::{parse_front, ParseCursor, StreamParser};
+pub use transport::{Transport, StreamTransport, LoopbackTransport, FileReplayTransport, RecordingTransport, ReplayTransport, send_to_framed, recv_framed};
+
+/// データサイズ部の位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPosition {
+    /// ヘッダの直後に置く（デフォルト）
+    Prefix,
+    /// メインデータの直後に置く．
+    /// 送信前にデータ長が分からないストリーミング用のエンコーダ向け．
+    Suffix,
+}
+
+/// データサイズ部の幅
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthWidth {
+    /// 2Byte．上位バイトのMSBを1にする（デフォルト）
+    Bits15,
+    /// 4Byte．MSBの目印は付けず，固定値の直後に置く．
+    /// 大きなデータを送る転送路向け．
+    Bits32,
+}
+
+impl LengthWidth {
+    /// データサイズ部のByte数
+    fn size(self) -> usize {
+        match self {
+            LengthWidth::Bits15 => 2,
+            LengthWidth::Bits32 => 4,
+        }
+    }
+
+    /// 送信できるメインデータの最大Byte数
+    fn max_len(self) -> usize {
+        match self {
+            LengthWidth::Bits15 => 0x7F,
+            LengthWidth::Bits32 => u32::MAX as usize,
+        }
+    }
+}
+
+/// 4Byteのデータサイズ部のバイト順
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// パケットの終端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    /// 末尾にフッタ（Framing::footer）を置く（デフォルト）
+    Footer,
+    /// フッタを置かず，データサイズ部から求めた位置（チェックサム）でパケットを終える．
+    /// パケットを区切り無しで連続して送る相手向け．
+    /// エスケープ（Framing::escape）はフッタで区切ることが前提なので併用できない．
+    None,
+}
+
+impl Terminator {
+    /// フッタのByte数
+    fn size(self) -> usize {
+        match self {
+            Terminator::Footer => 1,
+            Terminator::None   => 0,
+        }
+    }
+}
+
+/// エスケープする場合に，チェックサムをエスケープの前後どちらのバイト列で計算するか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStage {
+    /// エスケープする前のバイト列で計算する（デフォルト）
+    BeforeEscape,
+    /// エスケープした後のバイト列で計算する．チェックサム自身はその後でエスケープする．
+    AfterEscape,
+}
+
+/// チェックサムの計算範囲
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumCoverage {
+    /// メインデータのみ（デフォルト）
+    PayloadOnly,
+    /// データサイズ部とメインデータ
+    LengthAndPayload,
+}
 
+/// パケットの形式設定
+/// 値の組み合わせによっては解析が曖昧になるので，make_packet_withとparser_withは
+/// 最初にvalidateで確認し，不適切な設定であればInvalidFramingを返す．
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framing {
+    /// ヘッダ（デフォルトは[0xA5, 0x5A]）
+    pub header: &'static [u8],
+    /// 固定値（デフォルトは0xA0）
+    pub constant: u8,
+    /// フッタ（デフォルトは0x04）
+    pub footer: u8,
+    pub length_position: LengthPosition,
+    pub length_width: LengthWidth,
+    /// LengthWidth::Bits32の場合のみ使う．
+    pub length_endian: Endian,
+    /// データサイズ部の値とメインデータのByte数の差（デフォルトは0）．
+    /// データサイズ部にチェックサムとフッタの分を含める相手では2にする（データサイズ部の値 = メインデータのByte数 + 2）．
+    pub length_offset: i8,
+    /// エスケープに使うバイト．Noneの場合はエスケープしない（デフォルト）．
+    /// 指定した場合，ヘッダとフッタ以外の全てのバイト（データサイズ部，固定値，チェックサムを含む）
+    /// に含まれるフッタと同じ値とエスケープバイト自身を[escape, byte ^ 0x20]に置き換える．
+    /// これによりパケット内にフッタが現れるのは末尾だけになるので，
+    /// 受信側はフッタで区切るだけでパケットを切り出せる．
+    pub escape: Option<u8>,
+    pub terminator: Terminator,
+    pub checksum_coverage: ChecksumCoverage,
+    /// チェックサムの種類（デフォルトはChecksumKind::Xor）．2Byteの種類ではチェックサム部も2Byteになる．
+    pub checksum_kind: ChecksumKind,
+    /// escapeを指定した場合のみ使う．
+    pub checksum_stage: ChecksumStage,
+    /// trueの場合，エスケープしない設定でメインデータにフッタと同じ値があれば，
+    /// make_packet_withはFooterByteInPayloadを返す（デフォルトはfalse）．
+    /// フッタで区切って受信する相手に送る場合に，パケットが途中で切られるのを送信側で検出する．
+    pub reject_footer_in_payload: bool,
+    /// パケット全体をこのByte数になるまでpad_byteで埋める．Noneの場合は埋めない（デフォルト）．
+    /// 埋めたバイトはパケットの終端より後ろに置くので，受信側は次のヘッダを探すときに読み飛ばす．
+    pub pad_to: Option<usize>,
+    /// 埋めるバイト（デフォルトは0x00）
+    pub pad_byte: u8,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            header: &[0xA5, 0x5A],
+            constant: 0xA0,
+            footer: 0x04,
+            length_position: LengthPosition::Prefix,
+            length_width: LengthWidth::Bits15,
+            length_endian: Endian::Big,
+            length_offset: 0,
+            escape: None,
+            terminator: Terminator::Footer,
+            checksum_coverage: ChecksumCoverage::PayloadOnly,
+            checksum_kind: ChecksumKind::Xor,
+            checksum_stage: ChecksumStage::BeforeEscape,
+            reject_footer_in_payload: false,
+            pad_to: None,
+            pad_byte: 0x00,
+        }
+    }
+}
+
+impl Framing {
+    /// 生成はできても確実に解析できないバイトの組み合わせを拒否する．
+    pub fn validate(&self) -> Result<(), PacketError> {
+        if self.header.is_empty() {
+            return Err(PacketError::InvalidFraming("The header is empty."));
+        }
+        if self.terminator == Terminator::Footer && self.header.contains(&self.footer) && !self.shares_delimiter() {
+            return Err(PacketError::InvalidFraming("The footer byte appears in the header."));
+        }
+        if self.shares_delimiter() && self.constant == self.footer {
+            // ヘッダの直後の区切りバイトは読み飛ばすので，固定値と区別できない
+            return Err(PacketError::InvalidFraming("The constant byte equals the shared delimiter."));
+        }
+        if let Some(escape) = self.escape {
+            if self.terminator != Terminator::Footer {
+                return Err(PacketError::InvalidFraming("Escaping requires the footer as a delimiter."));
+            } else if escape == self.footer {
+                return Err(PacketError::InvalidFraming("The escape byte equals the footer."));
+            } else if self.header.contains(&escape) {
+                return Err(PacketError::InvalidFraming("The escape byte appears in the header."));
+            } else if (escape ^ 0x20) == self.footer {
+                return Err(PacketError::InvalidFraming("The escaped form of the escape byte equals the footer."));
+            }
+        }
+        if self.pad_to.is_some() && self.header[0] == self.pad_byte {
+            return Err(PacketError::InvalidFraming("The padding byte equals the first header byte."));
+        }
+        Ok(())
+    }
+
+    /// ヘッダとフッタが同じ1Byte（HDLCの0x7E等）で，パケットの区切りを兼ねる場合にtrue．
+    /// 続けて送る場合は，前のパケットのフッタを次のパケットのヘッダとして使える．
+    /// 連続する区切りバイトは1つとして扱う．
+    pub(crate) fn shares_delimiter(&self) -> bool {
+        self.terminator == Terminator::Footer && self.header == [self.footer]
+    }
+
+    /// 最小のパケット（メインデータ1Byte）のByte数．
+    /// 区切りバイトを兼ねる場合は，続けて送ったときに共有する1Byteを除く．
+    pub(crate) fn min_frame_len(&self) -> usize {
+        let shared = if self.shares_delimiter() { 1 } else { 0 };
+        self.header.len() + self.length_width.size() + 1 + 1 + self.checksum_kind.width() + self.terminator.size() - shared
+    }
+
+    /// データサイズ部のバイト列を作る．
+    /// data_lenにlength_offsetを足した値が正であることは呼び出し側で確認しておく．
+    fn write_length(&self, packet: &mut Vec<u8>, data_len: usize) {
+        let data_len = self.declared_len(data_len);
+        match self.length_width {
+            LengthWidth::Bits15 => {
+                packet.push( (0x80 | (data_len >> 8)) as u8 );
+                packet.push( (0xFF & data_len) as u8 );
+            },
+            LengthWidth::Bits32 => {
+                let bytes = match self.length_endian {
+                    Endian::Big    => (data_len as u32).to_be_bytes(),
+                    Endian::Little => (data_len as u32).to_le_bytes(),
+                };
+                packet.extend_from_slice(&bytes);
+            },
+        }
+    }
+
+    /// チェックサムを計算する．先頭のchecksum_kind.width() Byteがチェックサム部になる．
+    /// length_field: データサイズ部のバイト列
+    fn checksum(&self, length_field: &[u8], main_data: &[u8]) -> [u8; 2] {
+        if let (ChecksumStage::AfterEscape, Some(escape)) = (self.checksum_stage, self.escape) {
+            // エスケープはバイト単位の置き換えなので，範囲ごとにエスケープしてから計算すればよい
+            let length_field = stuff(length_field, escape, self.footer);
+            let main_data = stuff(main_data, escape, self.footer);
+            let framing = Framing { checksum_stage: ChecksumStage::BeforeEscape, ..*self };
+            return framing.checksum(&length_field, &main_data);
+        }
+        let value = match self.checksum_coverage {
+            ChecksumCoverage::PayloadOnly => self.checksum_kind.compute(main_data),
+            ChecksumCoverage::LengthAndPayload => self.checksum_kind.compute_bytes(length_field.iter().chain(main_data).copied()),
+        };
+        self.checksum_kind.value_bytes(value)
+    }
+
+    /// データサイズ部を読み，メインデータのByte数を返す．
+    /// field: データサイズ部（length_width.size() Byte）
+    /// 2Byteの場合にMSBが1でなければNoneを返す．
+    fn read_length(&self, field: &[u8]) -> Option<usize> {
+        self.read_declared(field).map(|declared| self.data_len(declared))
+    }
+
+    /// データサイズ部の値をそのまま読む（length_offsetを引かない）．
+    fn read_declared(&self, field: &[u8]) -> Option<usize> {
+        match self.length_width {
+            LengthWidth::Bits15 => {
+                if (field[0] & 0x80) != 0x80 {
+                    return None;
+                }
+                Some( (((field[0] & 0x7F) as usize) << 8) | field[1] as usize )
+            },
+            LengthWidth::Bits32 => {
+                let bytes = [field[0], field[1], field[2], field[3]];
+                let len = match self.length_endian {
+                    Endian::Big    => u32::from_be_bytes(bytes),
+                    Endian::Little => u32::from_le_bytes(bytes),
+                };
+                Some(len as usize)
+            },
+        }
+    }
+
+    /// メインデータのByte数からデータサイズ部の値を求める．
+    fn declared_len(&self, data_len: usize) -> usize {
+        (data_len as isize + self.length_offset as isize).max(0) as usize
+    }
+
+    /// 送信し得るデータサイズ部の値の最大値（length_width.max_len()にlength_offsetを足したもの）
+    fn max_declared(&self) -> usize {
+        self.length_width.max_len().saturating_add_signed(self.length_offset as isize)
+    }
+
+    /// データサイズ部の値からメインデータのByte数を求める．
+    /// length_offsetより小さい値はメインデータの無いパケットとして扱う．
+    fn data_len(&self, declared: usize) -> usize {
+        (declared as isize - self.length_offset as isize).max(0) as usize
+    }
+}
+
+/// 解析の設定（parse_withで使う）
+/// ParseOptions::default()はparser_withにデフォルトのFramingを渡した場合と同じ動作になる．
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParseOptions {
+    pub framing: Framing,
+    /// trueの場合，ヘッダがoffsetの位置に無ければHeaderNotFoundを返す（前にあるゴミを許さない）．
+    /// max_scanとresyncより優先する．
+    pub strict: bool,
+    /// trueの場合，壊れたパケットはそのヘッダの次から探索し直し，後ろにある正常なパケットを返す．
+    /// 正常なパケットが無ければ最初の誤りを返す（途切れている場合はIncompleteかTruncatedBody）．
+    pub resync: bool,
+    /// ヘッダを探すのはoffsetからmax_scan Byte先までに限る．Noneの場合は最後まで探す．
+    pub max_scan: Option<usize>,
+    /// Someの場合，メインデータのサイズがこれと一致しなければLengthMismatchを返す．
+    pub expected_len: Option<usize>,
+    /// データサイズ部の値がこれを超えればDeclaredExceedsMaxを返す．
+    /// Noneの場合はFramingで送信し得る最大値（LengthWidth::Bits15では0x7F）を上限とし，
+    /// Someの場合はそれより小さい値だけが上限を狭める．
+    /// 受信側のバッファサイズ等に合わせて指定すると，壊れたデータサイズ部を待ち続けずに済む．
+    pub max_len: Option<usize>,
+}
+
+/// データサイズ部の値の確認に使う設定
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LengthLimits {
+    expected: Option<usize>,
+    max: Option<usize>,
+}
+
+impl LengthLimits {
+    /// データサイズ部の値declaredが上限を超えておらず，メインデータのByte数data_sizeがexpectedと一致するか確認する．
+    /// 上限はmaxとframingで送信し得る最大値の小さい方．
+    fn check(&self, declared: usize, data_size: usize, framing: &Framing) -> Result<(), PacketError> {
+        let max = self.max.map_or(framing.max_declared(), |max| max.min(framing.max_declared()));
+        if declared > max {
+            return Err(PacketError::DeclaredExceedsMax { declared, max });
+        }
+        match self.expected {
+            Some(expected) if data_size != expected => Err(PacketError::LengthMismatch { expected, actual: data_size }),
+            _ => Ok(()),
+        }
+    }
+}
 
 /// パケットを生成
 /// 送信できるデータは最大128Byte（データサイズ部が7bitであるため）．
-pub fn make_packet(data: &mut Vec<u8>) -> Result<Vec<u8>, &'static str> {
+pub fn make_packet(data: &mut Vec<u8>) -> Result<Vec<u8>, PacketError> {
+    make_packet_with(data, &Framing::default())
+}
+
+/// 形式を指定してパケットを生成
+/// LengthWidth::Bits32の場合は最大u32::MAX Byteまで送信できる．
+/// Framing::pad_toを指定した場合は，フッタの後ろを埋めて全てのパケットを同じ長さにする．
+pub fn make_packet_with(data: &mut Vec<u8>, framing: &Framing) -> Result<Vec<u8>, PacketError> {
+    check_payload(data, framing)?;
     let data_len = data.len();
-    if data_len == 0 {
-        return Err("The main data size is 0.");
-    } else if data_len > 0x7F {
-        return Err("The data size exceeds the maximum value that can be sent in this packet.");
-    }
 
-    let mut packet: Vec<u8> = Vec::with_capacity(data_len + 7);
+    let mut packet: Vec<u8> = Vec::with_capacity(data_len + framing.header.len() + framing.length_width.size() + 4);
+    let mut length_field: Vec<u8> = Vec::with_capacity(4);
+    framing.write_length(&mut length_field, data_len);
+    let checksum = framing.checksum(&length_field, data);
     // Header
-    packet.push(0xA5);
-    packet.push(0x5A);
-    // Data size
-    packet.push( (0x80 | (data_len >> 8)) as u8 );
-    packet.push( (0xFF & data_len) as u8 );
-    // Const
-    packet.push(0xA0);
-    let checksum = calc_checksum(&data);
-    // Set main data
-    packet.append(data);
+    packet.extend_from_slice(framing.header);
+    match (framing.length_position, framing.length_width) {
+        (LengthPosition::Prefix, LengthWidth::Bits15) => {
+            // Data size
+            framing.write_length(&mut packet, data_len);
+            // Const
+            packet.push(framing.constant);
+            // Set main data
+            packet.append(data);
+        },
+        (LengthPosition::Prefix, LengthWidth::Bits32) => {
+            // Const
+            packet.push(framing.constant);
+            // Data size
+            framing.write_length(&mut packet, data_len);
+            // Set main data
+            packet.append(data);
+        },
+        (LengthPosition::Suffix, _) => {
+            // Const
+            packet.push(framing.constant);
+            // Set main data
+            packet.append(data);
+            // Data size
+            framing.write_length(&mut packet, data_len);
+        },
+    }
     // Checksum
-    packet.push(checksum);
+    packet.extend_from_slice(&checksum[..framing.checksum_kind.width()]);
+
+    if let Some(escape) = framing.escape {
+        let stuffed = stuff(&packet[framing.header.len()..], escape, framing.footer);
+        packet.truncate(framing.header.len());
+        packet.extend(stuffed);
+    }
+
     // Footer
-    packet.push(0x04);
+    if framing.terminator == Terminator::Footer {
+        packet.push(framing.footer);
+    }
+
+    // データサイズ部が後ろにある場合，メインデータの途中に整合する終端があると受信側で短いパケットとして読まれる
+    if framing.length_position == LengthPosition::Suffix && framing.escape.is_none() {
+        match parse_suffix(&packet, 0, framing.header.len(), framing) {
+            Ok((main_data, _, _)) if main_data.len() == data_len => {},
+            _ => return Err(PacketError::FooterByteInPayload),
+        }
+    }
+
+    // Padding
+    if let Some(pad_to) = framing.pad_to {
+        if packet.len() > pad_to {
+            return Err(PacketError::DataTooLong);
+        }
+        packet.resize(pad_to, framing.pad_byte);
+    }
 
     Ok(packet)
 }
 
+/// make_packet_withと同じパケットを，中間のバッファを作らずにwへ順に書き込む．
+/// メインデータが大きい場合にコピーを減らせる．
+/// パケットを生成できない場合は，何も書き込まずにPacketErrorをInvalidInputのio::Errorにして返す．
+pub fn encode_to_writer<W: Write>(data: &[u8], framing: &Framing, w: &mut W) -> io::Result<()> {
+    let invalid = |e: PacketError| io::Error::new(io::ErrorKind::InvalidInput, e);
+    check_payload(data, framing).map_err(invalid)?;
+    // 終端の候補を確認するには組み立てる必要がある
+    if framing.length_position == LengthPosition::Suffix && framing.escape.is_none() {
+        let packet = make_packet_with(&mut data.to_vec(), framing).map_err(invalid)?;
+        return w.write_all(&packet);
+    }
+
+    let mut length_field: Vec<u8> = Vec::with_capacity(4);
+    framing.write_length(&mut length_field, data.len());
+    let checksum = framing.checksum(&length_field, data);
+    let checksum = &checksum[..framing.checksum_kind.width()];
+    let constant = [framing.constant];
+    let parts: [&[u8]; 4] = match (framing.length_position, framing.length_width) {
+        (LengthPosition::Prefix, LengthWidth::Bits15) => [&length_field, &constant, data, checksum],
+        (LengthPosition::Prefix, LengthWidth::Bits32) => [&constant, &length_field, data, checksum],
+        (LengthPosition::Suffix, _) => [&constant, data, &length_field, checksum],
+    };
+    let footer: &[u8] = if framing.terminator == Terminator::Footer { &[framing.footer] } else { &[] };
+
+    // 書き込む前にパケットの長さを確認する
+    let len = framing.header.len() + parts.iter().map(|part| stuffed_len(part, framing)).sum::<usize>() + footer.len();
+    let padding = match framing.pad_to {
+        Some(pad_to) if len > pad_to => return Err(invalid(PacketError::DataTooLong)),
+        Some(pad_to) => pad_to - len,
+        None => 0,
+    };
+
+    w.write_all(framing.header)?;
+    for part in &parts {
+        write_stuffed(w, part, framing)?;
+    }
+    w.write_all(footer)?;
+    for _ in 0..padding {
+        w.write_all(&[framing.pad_byte])?;
+    }
+    Ok(())
+}
+
+/// デフォルトのFramingのパケットをoutの先頭に書き込み，書き込んだ範囲を返す．
+/// 送信用のリングバッファから切り出した領域に直接パケットを作る場合に使う．
+/// outに収まらない場合は，何も書き込まずにDataTooLongを返す．
+pub fn encode_into_slice<'a>(data: &[u8], out: &'a mut [u8]) -> Result<&'a mut [u8], PacketError> {
+    let framing = Framing::default();
+    check_payload(data, &framing)?;
+    // ヘッダ，データサイズ部，固定値，チェックサム，フッタ
+    let len = framing.header.len() + framing.length_width.size() + data.len() + 3;
+    if out.len() < len {
+        return Err(PacketError::DataTooLong);
+    }
+    // 長さは確認済みなので書き込みは失敗しない
+    encode_to_writer(data, &framing, &mut &mut out[..len]).unwrap();
+    Ok(&mut out[..len])
+}
+
+/// メインデータをパケットにできるか確認する．
+fn check_payload(data: &[u8], framing: &Framing) -> Result<(), PacketError> {
+    framing.validate()?;
+    if data.is_empty() {
+        return Err(PacketError::EmptyData);
+    } else if data.len() > framing.length_width.max_len() {
+        return Err(PacketError::DataTooLong);
+    } else if (data.len() as isize + framing.length_offset as isize) <= 0 {
+        return Err(PacketError::InvalidFraming("The length offset makes the data size part zero or negative."));
+    }
+    let delimited = framing.escape.is_none() && framing.terminator == Terminator::Footer;
+    if framing.reject_footer_in_payload && delimited && data.contains(&framing.footer) {
+        return Err(PacketError::FooterByteInPayload);
+    }
+    Ok(())
+}
+
+/// エスケープした後のByte数
+fn stuffed_len(data: &[u8], framing: &Framing) -> usize {
+    match framing.escape {
+        Some(escape) => data.len() + data.iter().filter(|&&byte| byte == escape || byte == framing.footer).count(),
+        None => data.len(),
+    }
+}
+
+/// エスケープしながらwに書き込む．置き換えるバイトの間はまとめて書き込む．
+fn write_stuffed<W: Write>(w: &mut W, data: &[u8], framing: &Framing) -> io::Result<()> {
+    let escape = match framing.escape {
+        Some(escape) => escape,
+        None => return w.write_all(data),
+    };
+    let mut rest = data;
+    while let Some(pos) = rest.iter().position(|&byte| byte == escape || byte == framing.footer) {
+        w.write_all(&rest[..pos])?;
+        w.write_all(&[escape, rest[pos] ^ 0x20])?;
+        rest = &rest[(pos + 1)..];
+    }
+    w.write_all(rest)
+}
+
+/// srcの形式のパケットをdstの形式のパケットに変換する．
+/// メインデータを取り出し，チェックサムはdstの形式で計算し直す．異なる形式の機器の間を中継する場合に使う．
+/// frameの先頭からsrcの形式で読むので，前にゴミがあれば読み飛ばす．
+pub fn reframe(frame: &[u8], src: &Framing, dst: &Framing) -> Result<Vec<u8>, PacketError> {
+    let (mut main_data, _, _) = parser_with(frame, 0, src)?;
+    make_packet_with(&mut main_data, dst)
+}
+
+/// 形式の候補を優先順に試し，最初に正常なパケットを読めた形式で解析する．
+/// どの形式で作られたか分からないデータを調べる場合に使う．
+/// return: (main_data, 一致した候補のindex)
+/// どの候補でも読めなければ，最初の候補で解析したときのエラーを返す．
+pub fn decode_with_framing_guess(packet: &[u8], presets: &[&Framing]) -> Result<(Vec<u8>, usize), PacketError> {
+    let mut first_error = None;
+    for (index, framing) in presets.iter().enumerate() {
+        match parser_with(packet, 0, framing) {
+            Ok((main_data, _, _)) => return Ok((main_data, index)),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            },
+        }
+    }
+    Err(first_error.unwrap_or(PacketError::InvalidFraming("No framing preset is given.")))
+}
+
+/// 固定の制御パケット（開始，停止，ping等）を一度だけ生成して使い回す．
+/// 同じデータに対しては常に同じスライスを返すので，ループ内で毎回パケットを組み立てずに済む．
+///
+/// 生成したパケットはプログラム終了まで解放しない（Box::leakでリークさせる）．
+/// 1種類のデータにつき「パケット長 + キャッシュのキー（データ長）」分のメモリを使い続けるので，
+/// 種類の限られた固定データにのみ使うこと．任意のデータを渡すとメモリを消費し続ける．
+pub fn encode_static(data: &[u8]) -> Result<&'static [u8], PacketError> {
+    static CACHE: OnceLock<Mutex<HashMap<Vec<u8>, &'static [u8]>>> = OnceLock::new();
+
+    let mut cache = CACHE.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+    if let Some(&packet) = cache.get(data) {
+        return Ok(packet);
+    }
+    let packet: &'static [u8] = Box::leak(make_packet(&mut data.to_vec())?.into_boxed_slice());
+    cache.insert(data.to_vec(), packet);
+    Ok(packet)
+}
+
 /// バッファ内を操作してメインデータ部を見つける．
 /// packet: 受信したパケットないしはそれが含まれるバッファ．
 /// offset: バッファ内のoffset番目から走査を行う．普通はoffset=0とする．
@@ -46,100 +597,531 @@ pub fn make_packet(data: &mut Vec<u8>) -> Result<Vec<u8>, &'static str> {
 /// main_data: パケット内のメインデータ部
 /// head_pos : packet内でヘッダを見つけた位置
 /// tail_pos : packet内でのパケットの終端位置
-pub fn parser(packet: &Vec<u8>, offset: usize) -> Result<(Vec<u8>, usize, usize), &'static str> {
+pub fn parser(packet: &[u8], offset: usize) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    parser_with(packet, offset, &Framing::default())
+}
+
+/// 形式を指定してバッファ内からメインデータ部を見つける．
+/// 引数と戻り値はparserと同じ．
+pub fn parser_with(packet: &[u8], offset: usize, framing: &Framing) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    parse_with(packet, offset, &ParseOptions { framing: *framing, ..ParseOptions::default() })
+}
+
+/// parserと同じだが，ヘッダを探すのはoffsetからmax_scan Byte先まで（ヘッダの先頭がoffset + max_scan以下）に限る．
+/// その範囲にヘッダが無ければHeaderNotFoundを返すので，同期がずれた大きなバッファを最後まで探索せずに済む．
+pub fn parser_bounded(packet: &[u8], offset: usize, max_scan: usize) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    parse_with(packet, offset, &ParseOptions { max_scan: Some(max_scan), ..ParseOptions::default() })
+}
+
+/// 設定を指定してバッファ内からメインデータ部を見つける．
+/// 戻り値はparserと同じ．
+pub fn parse_with(packet: &[u8], offset: usize, options: &ParseOptions) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    let framing = &options.framing;
+    framing.validate()?;
+    // ヘッダの先頭として許す最後の位置
+    let last = if options.strict { Some(offset) } else { options.max_scan.map(|max_scan| offset.saturating_add(max_scan)) };
+
+    let mut first_error = None;
+    let mut pos = offset;
+    loop {
+        let max_scan = match last {
+            Some(last) if last < pos => return Err(first_error.unwrap_or(PacketError::HeaderNotFound)),
+            Some(last) => Some(last - pos),
+            None => None,
+        };
+        let head_pos = match find_header_within(packet, pos, framing.header, max_scan) {
+            Ok(head_pos) => skip_delimiters(packet, head_pos, framing),
+            Err(e) => return Err(first_error.unwrap_or(e)),
+        };
+
+        let limits = LengthLimits { expected: options.expected_len, max: options.max_len };
+        match parse_at(packet, head_pos, framing, limits) {
+            Err(e) if options.resync && !options.strict && !e.is_retryable() => {
+                first_error.get_or_insert(e);
+                pos = head_pos + 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// 区切りバイトが続いている場合は最後のものをヘッダとする．
+pub(crate) fn skip_delimiters(packet: &[u8], mut head_pos: usize, framing: &Framing) -> usize {
+    if framing.shares_delimiter() {
+        while packet.get(head_pos + 1) == Some(&framing.footer) {
+            head_pos += 1;
+        }
+    }
+    head_pos
+}
+
+/// head_posにあるヘッダ以降を読む．
+fn parse_at(packet: &[u8], head_pos: usize, framing: &Framing, limits: LengthLimits) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    let result = match framing.escape {
+        Some(escape) => parse_escaped(packet, head_pos, escape, framing),
+        None => return parse_layout(packet, head_pos, framing, limits),
+    };
+
+    // エスケープする形式では，読み終えてからサイズを確認する
+    let (main_data, head_pos, tail_pos) = result?;
+    limits.check(framing.declared_len(main_data.len()), main_data.len(), framing)?;
+    Ok((main_data, head_pos, tail_pos))
+}
+
+/// エスケープされていないパケットを，データサイズ部の位置に応じて読む．
+fn parse_layout(packet: &[u8], head_pos: usize, framing: &Framing, limits: LengthLimits) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    match framing.length_position {
+        LengthPosition::Prefix => {
+            let (main_data, _, tail_pos) = parse_prefix(packet, head_pos + framing.header.len(), framing, false, limits)?;
+            Ok((main_data.to_vec(), head_pos, tail_pos))
+        },
+        LengthPosition::Suffix => {
+            let (main_data, head_pos, tail_pos) = parse_suffix(packet, head_pos, head_pos + framing.header.len(), framing)?;
+            limits.check(framing.declared_len(main_data.len()), main_data.len(), framing)?;
+            Ok((main_data, head_pos, tail_pos))
+        },
+    }
+}
+
+/// parserと同じ解析を行い，結果をstatsに集計する．
+pub fn parse_with_stats(packet: &[u8], offset: usize, stats: &mut DecoderStats) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    let result = parser(packet, offset);
+    match result {
+        Ok((_, head_pos, _)) => {
+            stats.ok += 1;
+            stats.skipped_bytes += (head_pos - offset) as u64;
+            return result;
+        },
+        Err(PacketError::HeaderNotFound) => {
+            stats.header_not_found += 1;
+            stats.skipped_bytes += (packet.len() - offset) as u64;
+            return result;
+        },
+        Err(PacketError::ChecksumMismatch { .. }) => stats.checksum_error += 1,
+        Err(e) if e.is_retryable() => stats.incomplete += 1,
+        Err(_) => stats.format_error += 1,
+    }
+    // ヘッダは見つかったがその後で失敗した場合
+    if let Ok(head_pos) = find_header(packet, offset, Framing::default().header) {
+        stats.skipped_bytes += (head_pos - offset) as u64;
+    }
+    result
+}
+
+/// 回線品質の推定用にチェックサムが一致しないパケットも読み出す．
+/// 引数はparserと同じ．
+/// return: (main_data, head_pos, tail_pos, distance)
+/// distance: 計算したチェックサムと受信したチェックサムで異なるビットの数．
+///           多数のパケットで平均をとると，おおよそのビット誤り率の目安になる．
+/// チェックサム以外の整合性（ヘッダ，固定値，フッタ等）はparserと同様に確認する．
+pub fn parser_lossy(packet: &[u8], offset: usize) -> Result<(Vec<u8>, usize, usize, u32), PacketError> {
+    let framing = Framing::default();
+    let head_pos = find_header(packet, offset, framing.header)?;
+    let (main_data, distance, tail_pos) = parse_prefix(packet, head_pos + framing.header.len(), &framing, true, LengthLimits::default())?;
+    Ok((main_data.to_vec(), head_pos, tail_pos, distance))
+}
+
+/// メインデータのサイズがNに決まっているパケットをバッファの先頭から探して読み，配列で返す．
+/// ヒープを使わない．データサイズ部の値がNでない場合はLengthMismatchを返す．
+/// デフォルトのFramingのパケットのみ扱う．
+pub fn parse_fixed<const N: usize>(packet: &[u8]) -> Result<[u8; N], PacketError> {
+    let framing = Framing::default();
+    let head_pos = find_header(packet, 0, framing.header)?;
+    let (main_data, _, _) = parse_prefix(packet, head_pos + framing.header.len(), &framing, false, LengthLimits { expected: Some(N), max: None })?;
+    let mut data = [0u8; N];
+    data.copy_from_slice(main_data);
+    Ok(data)
+}
+
+/// 受信したメインデータを区切り文字で分割する．
+/// メインデータ内がカンマ区切りのセンサ値などになっている場合に使う．
+pub fn split_payload(main_data: &[u8], delim: u8) -> impl Iterator<Item = &[u8]> {
+    main_data.split(move |&byte| byte == delim)
+}
+
+/// ヘッダ等を付けずに，データの後ろにチェックサム（デフォルトのFramingと同じXOR）だけを付ける．
+/// 区切りを自分で扱う下位の層で，整合性の確認だけを使う場合に使う．
+pub fn append_integrity(data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(data.len() + 1);
+    buf.extend_from_slice(data);
+    buf.push(calc_checksum(data));
+    buf
+}
+
+/// append_integrityで付けた末尾のチェックサムを確認し，それを除いたデータを返す．
+/// バッファが空の場合はIncomplete，一致しない場合はChecksumMismatch（posは末尾）を返す．
+pub fn verify_and_strip(buf: &[u8]) -> Result<&[u8], PacketError> {
+    let (&checksum, data) = buf.split_last().ok_or(PacketError::Incomplete { needed: Some(1) })?;
+    if calc_checksum(data) != checksum {
+        return Err(PacketError::ChecksumMismatch { pos: data.len() });
+    }
+    Ok(data)
+}
+
+/// バッファの先頭から，どのパケットの一部にもなり得ないByte数を返す（最初のヘッダの位置）．
+/// パケットの中身は解析しない．受信バッファの先頭からこの分を捨ててから解析すればよい．
+/// ヘッダが無い場合は，末尾の途中まで届いたヘッダの可能性がある部分を除いたByte数を返す
+/// （[0x45, 0x22, 0xA5]なら2）．buf.len()を返すと，続きが届けば完成するヘッダの先頭まで捨ててしまうため．
+/// デフォルトのFramingのヘッダを探す．
+pub fn drain_garbage(buf: &[u8]) -> usize {
+    let header = Framing::default().header;
+    match find_header(buf, 0, header) {
+        Ok(head_pos) => head_pos,
+        Err(_) => {
+            // 末尾がヘッダの先頭部分と一致する場合は残す
+            let keep = (1..header.len()).rev().find(|&n| buf.ends_with(&header[..n])).unwrap_or(0);
+            buf.len() - keep
+        },
+    }
+}
+
+/// パケット（先頭がヘッダのもの）のメインデータのoffset Byte目以降をnew_bytesで書き換え，チェックサムを更新する．
+/// データサイズは変わらないので，パケットを組み立て直さずに転送できる．
+/// デフォルトのFramingのパケットのみ扱う．
+/// 書き換える範囲がメインデータを超える場合はOffsetOutOfRangeを返し，パケットは変更しない．
+pub fn patch_payload(frame: &mut [u8], offset: usize, new_bytes: &[u8]) -> Result<(), PacketError> {
+    let framing = Framing::default();
+    if !frame.starts_with(framing.header) {
+        return Err(PacketError::HeaderNotFound);
+    }
+    let i = framing.header.len();
+    if frame.len() < i + 2 {
+        return Err(PacketError::Incomplete { needed: None });
+    } else if (frame[i] & 0x80) != 0x80 {
+        return Err(PacketError::InvalidLengthMarker { pos: i });
+    }
+    let data_size = framing.read_length(&frame[i..(i + 2)]).unwrap();
+    let data_head = i + 3;
+    let checksum_pos = data_head + data_size;
+    if frame.len() <= checksum_pos {
+        return Err(PacketError::Incomplete { needed: Some(checksum_pos + 2 - frame.len()) });
+    }
+    if offset.checked_add(new_bytes.len()).is_none_or(|end| end > data_size) {
+        return Err(PacketError::OffsetOutOfRange);
+    }
+
+    // XORなので，書き換えたバイトの差分だけチェックサムに反映すればよい
+    let target = &mut frame[(data_head + offset)..(data_head + offset + new_bytes.len())];
+    let mut diff = 0;
+    for (old, &new) in target.iter_mut().zip(new_bytes) {
+        diff ^= *old ^ new;
+        *old = new;
+    }
+    frame[checksum_pos] ^= diff;
+    Ok(())
+}
+
+/// offset番目以降でヘッダを探し，その位置を返す．
+pub(crate) fn find_header(packet: &[u8], offset: usize, header: &[u8]) -> Result<usize, PacketError> {
+    find_header_within(packet, offset, header, None)
+}
+
+/// offset番目からmax_scan Byte先までの位置でヘッダを探す．max_scanがNoneの場合は最後まで探す．
+fn find_header_within(packet: &[u8], offset: usize, header: &[u8], max_scan: Option<usize>) -> Result<usize, PacketError> {
     let packet_len = packet.len();
-    let mut i: usize = offset;
 
     if packet_len <= offset {
-        return Err("Packet size shorter than offset position.");
-    } else if packet_len <= 7 {
-        return Err("Read data is 7Byte or less.");
+        return Err(PacketError::OffsetOutOfRange);
     }
 
     // ヘッダを探す
-    let mut header_flag = false;
-    let mut head_pos = 0;
-    for _ in offset..(packet_len - 1) {
-        if packet[i] == 0xA5 {
-            if packet[i+1] == 0x5A {
-                header_flag = true;
-                head_pos = i;
-                i += 2;
-                break;
-            }
-        }
-        i += 1;
+    let end = match max_scan {
+        Some(max_scan) => packet_len.min(offset.saturating_add(max_scan).saturating_add(header.len())),
+        None => packet_len,
+    };
+    if let Some(pos) = packet[offset..end].windows(header.len()).position(|window| window == header) {
+        return Ok(offset + pos);
     }
 
     // ヘッダを読み出せずに最後まで行ってしまった場合の処理
-    if header_flag == false {
-        return Err("Header does not exist.");
-    }
+    Err(PacketError::HeaderNotFound)
+}
 
-    // バッファオーバーラン対策
-    // バッファ内でパケットが途切れている可能性がある
-    if (packet_len - i) < 3 {
-        return Err("Data size part and constant part do not fit in buffer.");
-    }
+/// parse_prefixが読んだ各フィールドの位置（diagnostics::explainで使う）
+/// バッファ内にあって値を確認したフィールドだけがSomeになる．
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FieldTrace {
+    /// (データサイズ部の位置, データサイズ部の値, メインデータのByte数)
+    pub length: Option<(usize, usize, usize)>,
+    /// 固定値の位置
+    pub constant: Option<usize>,
+    /// メインデータの範囲
+    pub payload: Option<(usize, usize)>,
+    /// (チェックサム部の位置, 計算したチェックサム)
+    pub checksum: Option<(usize, [u8; 2])>,
+    /// フッタの位置
+    pub footer: Option<usize>,
+}
 
-    // データ長を読む
-    let data_size: usize;
-    if (packet[i] & 0x80) == 0x80 {
-        let tmp_h = ( (packet[i] & 0x7F ) as usize) << 8;
+/// データサイズ部がヘッダ直後にあるパケットを読む．
+/// i: ヘッダの次の位置
+/// lossy: trueの場合はチェックサムが一致しなくてもエラーにしない．
+/// limits: データサイズ部の値を読んだ時点で確認する．
+/// return: (main_data, 計算したチェックサムと受信したチェックサムで異なるビットの数, tail_pos)
+///         main_dataはpacket内のスライスなので，呼び出し側で必要な分だけコピーする．
+pub(crate) fn parse_prefix<'a>(packet: &'a [u8], i: usize, framing: &Framing, lossy: bool, limits: LengthLimits) -> Result<(&'a [u8], u32, usize), PacketError> {
+    parse_prefix_traced(packet, i, framing, lossy, limits, &mut FieldTrace::default())
+}
+
+/// parse_prefixと同じだが，読んだフィールドの位置をtraceに記録する．
+pub(crate) fn parse_prefix_traced<'a>(packet: &'a [u8], mut i: usize, framing: &Framing, lossy: bool, limits: LengthLimits, trace: &mut FieldTrace) -> Result<(&'a [u8], u32, usize), PacketError> {
+    let packet_len = packet.len();
+    let head_pos = i - framing.header.len();
+    let width = framing.length_width.size();
+    let checksum_width = framing.checksum_kind.width();
+    // チェックサムとフッタのByte数
+    let trailer = checksum_width + framing.terminator.size();
+
+    // 各フィールドは，バッファ内にある分だけ前から順に確認する．
+    // 途切れている場合でも，それまでに読めたバイトの誤りを優先して返す（PacketErrorの優先順位を参照）．
+    if framing.length_width == LengthWidth::Bits32 {
+        // 固定値がデータサイズ部の前にある
+        if packet_len <= i {
+            return Err(PacketError::Incomplete { needed: None });
+        }
+        trace.constant = Some(i);
+        if packet[i] != framing.constant {
+            return Err(PacketError::InvalidConstByte { pos: i });
+        }
         i += 1;
-        let tmp_l = packet[i] as usize;
-        data_size = tmp_h | tmp_l;
-    } else {
-        return Err("Syntax error (The 3rd byte MSB is not 1).");
     }
 
-    // メインデータ長が0ならエラーで返す．
+    // データ長を読む
+    if packet_len <= i {
+        return Err(PacketError::Incomplete { needed: None });
+    } else if framing.length_width == LengthWidth::Bits15 && (packet[i] & 0x80) != 0x80 {
+        return Err(PacketError::InvalidLengthMarker { pos: i });
+    } else if (packet_len - i) < width {
+        return Err(PacketError::Incomplete { needed: None });
+    }
+    let length_pos = i;
+    let declared = framing.read_declared(&packet[i..(i + width)]).unwrap();
+    let data_size = framing.data_len(declared);
+    trace.length = Some((length_pos, declared, data_size));
     if data_size == 0 {
-        return Err("Main data part is None.");
+        return Err(zero_length(packet, head_pos, length_pos, framing));
     }
+    limits.check(declared, data_size, framing)?;
+    i += width;
 
-    // 固定値を見てデータの整合性を確認
-    i += 1;
-    if packet[i] != 0xA0 {
-        return Err("Syntax error (The 5th of the packet is not 0xA0).");
+    // データサイズ部より後ろ（固定値，メインデータ，チェックサム，フッタ）のByte数
+    // 4Byteのデータサイズ部では，32bitの環境でパケットの終端の位置がusizeに収まらないことがある
+    let overhead = if framing.length_width == LengthWidth::Bits15 { 1 } else { 0 } + trailer;
+    let frame_end = match data_size.checked_add(i + overhead) {
+        Some(frame_end) => frame_end,
+        None => return Err(PacketError::DeclaredExceedsMax { declared, max: usize::MAX - (i + overhead) }),
+    };
+    let truncated = PacketError::TruncatedBody {
+        declared,
+        available: packet_len - head_pos,
+        needed: frame_end.saturating_sub(packet_len),
+    };
+
+    if framing.length_width == LengthWidth::Bits15 {
+        // 固定値を見てデータの整合性を確認
+        if packet_len <= i {
+            return Err(truncated);
+        }
+        trace.constant = Some(i);
+        if packet[i] != framing.constant {
+            return Err(PacketError::InvalidConstByte { pos: i });
+        }
+        i += 1;
     }
 
     // バッファオーバーラン対策
     // メインデータ部以降のデータが残りのバッファサイズを超えていた場合の処理
-    if (packet_len - i) < (data_size + 3) {
-        return Err("The data after the main data section does not fit in the buffer.");
+    if packet_len < frame_end {
+        return Err(truncated);
     }
 
     // メインデータを読む
-    let mut main_data: Vec<u8> = Vec::with_capacity(data_size);
-    i += 1;
-    for j in i..(i + data_size) {
-        main_data.push( packet[j] );
-    }
+    let main_data = &packet[i..(i + data_size)];
+    trace.payload = Some((i, i + data_size));
     i += data_size;
 
     // チェックサムで整合性を確認
-    if ( calc_checksum(&main_data) ^ packet[i] ) != 0 {
-        return Err("Checksum mismatch.");
+    let checksum = framing.checksum(&packet[length_pos..(length_pos + width)], main_data);
+    trace.checksum = Some((i, checksum));
+    let distance = checksum[..checksum_width].iter()
+        .zip(&packet[i..(i + checksum_width)])
+        .map(|(a, b)| (a ^ b).count_ones())
+        .sum();
+    if distance != 0 && !lossy {
+        return Err(PacketError::ChecksumMismatch { pos: i });
     }
+    i += checksum_width - 1;
 
     // Footer
-    i += 1;
-    if packet[i] != 0x04 {
-        return Err("Footer does not exist.");
+    if framing.terminator == Terminator::Footer {
+        i += 1;
+        trace.footer = Some(i);
+        if packet[i] != framing.footer {
+            return Err(PacketError::FooterMissing { pos: i });
+        }
+    }
+
+    Ok((main_data, distance, i))
+}
+
+/// データサイズ部の値が0の場合に，残りがメインデータの無いパケット（キープアライブ等）として整合するかを確認する．
+/// 整合すればZeroLength，食い違えばデータサイズ部が壊れているとしてNoMainDataを返す．
+/// メインデータの無いパケットのチェックサムは，メインデータを0Byteとして計算した値とする．
+fn zero_length(packet: &[u8], head_pos: usize, length_pos: usize, framing: &Framing) -> PacketError {
+    let width = framing.length_width.size();
+    let mut expected = Vec::with_capacity(4);
+    if framing.length_width == LengthWidth::Bits15 {
+        expected.push(framing.constant);
+    }
+    let checksum = framing.checksum(&packet[length_pos..(length_pos + width)], &[]);
+    expected.extend_from_slice(&checksum[..framing.checksum_kind.width()]);
+    if framing.terminator == Terminator::Footer {
+        expected.push(framing.footer);
+    }
+
+    let rest = &packet[(length_pos + width)..];
+    for (k, &byte) in expected.iter().enumerate() {
+        match rest.get(k) {
+            None => {
+                let declared = framing.read_declared(&packet[length_pos..(length_pos + width)]).unwrap();
+                return PacketError::TruncatedBody { declared, available: packet.len() - head_pos, needed: expected.len() - k };
+            },
+            Some(&received) if received != byte => return PacketError::NoMainData { pos: length_pos },
+            _ => {},
+        }
+    }
+    PacketError::ZeroLength { pos: length_pos }
+}
+
+/// データサイズ部がメインデータの後ろにあるパケットを読む．
+/// フッタの候補を前から順に探し，そこから逆算したデータサイズ部と
+/// チェックサムが整合するものをパケットの終端とする．
+/// Terminator::Noneの場合は，全ての位置をチェックサムの候補として同様に探す．
+/// i: ヘッダの次の位置
+fn parse_suffix(packet: &[u8], head_pos: usize, i: usize, framing: &Framing) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    let packet_len = packet.len();
+    let width = framing.length_width.size();
+
+    // バッファオーバーラン対策
+    if packet_len <= i {
+        return Err(PacketError::Incomplete { needed: None });
+    }
+
+    // 固定値を見てデータの整合性を確認
+    if packet[i] != framing.constant {
+        return Err(PacketError::InvalidConstByte { pos: i });
+    }
+    let data_head = i + 1;
+
+    // メインデータ（1Byte以上），データサイズ部，チェックサムの後ろにフッタが来る
+    let footer_len = framing.terminator.size();
+    let checksum_width = framing.checksum_kind.width();
+    // チェックサムが一致しなかった最後の候補の位置
+    let mut checksum_error = None;
+    for tail in (data_head + width + checksum_width + footer_len)..packet_len {
+        if footer_len == 1 && packet[tail] != framing.footer {
+            continue;
+        }
+        let checksum_pos = tail + 1 - footer_len - checksum_width;
+        let data_size = match framing.read_length(&packet[(checksum_pos - width)..checksum_pos]) {
+            Some(data_size) => data_size,
+            None => continue,
+        };
+        if data_size == 0 || data_size != checksum_pos - width - data_head {
+            continue;
+        }
+
+        // チェックサムで整合性を確認
+        let main_data = &packet[data_head..(data_head + data_size)];
+        let length_field = &packet[(checksum_pos - width)..checksum_pos];
+        if framing.checksum(length_field, main_data)[..checksum_width] != packet[checksum_pos..(checksum_pos + checksum_width)] {
+            // メインデータ内にたまたまフッタと同じ並びがある可能性があるので探索を続ける
+            checksum_error = Some(checksum_pos);
+            continue;
+        }
+
+        return Ok((main_data.to_vec(), head_pos, tail));
+    }
+
+    // 整合するフッタが見つからない場合は，続きのデータが届いていない可能性がある
+    match checksum_error {
+        Some(pos) => Err(PacketError::ChecksumMismatch { pos }),
+        None => Err(PacketError::Incomplete { needed: None }),
     }
+}
+
+/// エスケープされたパケットを読む．
+/// ヘッダ以降で最初に現れるフッタまでを元に戻してから解析する．
+fn parse_escaped(packet: &[u8], head_pos: usize, escape: u8, framing: &Framing) -> Result<(Vec<u8>, usize, usize), PacketError> {
+    let body_pos = head_pos + framing.header.len();
+    let tail_pos = match packet[body_pos..].iter().position(|&byte| byte == framing.footer) {
+        Some(pos) => body_pos + pos,
+        None => return Err(PacketError::Incomplete { needed: None }),
+    };
+
+    let stuffed = &packet[head_pos..tail_pos];
+    let mut frame = unstuff(stuffed, escape).map_err(|e| e.with_base(head_pos))?;
+    frame.push(framing.footer);
 
-    Ok((main_data, head_pos, i))
+    // チェックサムの計算にエスケープバイトを使うことがあるので，framingはそのまま渡す
+    match parse_layout(&frame, 0, framing, LengthLimits::default()) {
+        Ok((main_data, _, _)) => Ok((main_data, head_pos, tail_pos)),
+        // フッタで区切った範囲にデータサイズ部の分のデータが無い
+        Err(e) if e.is_retryable() => Err(PacketError::FooterMissing { pos: tail_pos }),
+        // 元に戻したパケット内の位置を，エスケープされたバッファ内の位置に直す
+        Err(e) => Err(e.map_position(|pos| head_pos + stuffed_index(stuffed, escape, pos))),
+    }
+}
+
+/// unstuffした後のindexに対応する，stuffされたデータ内のindexを返す．
+/// エスケープされたバイトはエスケープバイトの位置を返す．
+/// indexがデータの範囲外（末尾に足したフッタ）の場合はdataの長さを返す．
+fn stuffed_index(data: &[u8], escape: u8, index: usize) -> usize {
+    let mut i = 0;
+    for _ in 0..index {
+        if i >= data.len() {
+            break;
+        }
+        i += if data[i] == escape { 2 } else { 1 };
+    }
+    i.min(data.len())
+}
+
+/// フッタとエスケープバイトを[escape, byte ^ 0x20]に置き換える．
+fn stuff(data: &[u8], escape: u8, footer: u8) -> Vec<u8> {
+    let mut stuffed = Vec::with_capacity(data.len() + 2);
+    for &byte in data {
+        if byte == footer || byte == escape {
+            stuffed.push(escape);
+            stuffed.push(byte ^ 0x20);
+        } else {
+            stuffed.push(byte);
+        }
+    }
+    stuffed
+}
+
+/// stuffで置き換えたバイトを元に戻す．
+fn unstuff(data: &[u8], escape: u8) -> Result<Vec<u8>, PacketError> {
+    let mut unstuffed = Vec::with_capacity(data.len());
+    let mut iter = data.iter().enumerate();
+    while let Some((i, &byte)) = iter.next() {
+        if byte == escape {
+            match iter.next() {
+                Some((_, &next)) => unstuffed.push(next ^ 0x20),
+                None => return Err(PacketError::InvalidEscape { pos: i }),
+            }
+        } else {
+            unstuffed.push(byte);
+        }
+    }
+    Ok(unstuffed)
 }
 
 /// データ部の各バイトのXORを計算する
 #[inline]
-fn calc_checksum(data: &Vec<u8>) -> u8 {
-    let mut num = data[0];
-    for i in 1..data.len() {
-        num ^= data[i];
-    }
-    num
+fn calc_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |num, &byte| num ^ byte)
 }
 
 
@@ -149,14 +1131,753 @@ mod tests {
     #[test]
     fn test_parser() {
         // 受信データの前後に関係無い値が存在しても受信データを取り出せる．
-        
+
         // 模擬受信データ
         let packet: Vec<u8> = vec![0x45, 0xA5, 0x22, 0x32,  // 関係ないデータ
             0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
         let (main_data, head, tail) = parser(&packet, 0).unwrap();
-    
+
         assert_eq!(main_data, vec![0x01, 0x23, 0xAB, 0xCD]);
         assert_eq!(head, 4);
         assert_eq!(tail, 14);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_length_suffix() {
+        let framing = Framing { length_position: LengthPosition::Suffix, ..Framing::default() };
+
+        let packet = make_packet_with(&mut vec![0x01, 0x23, 0xAB, 0xCD], &framing).unwrap();
+        assert_eq!(packet, vec![0xA5, 0x5A, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x80, 0x04, 0x44, 0x04]);
+
+        // メインデータ内にフッタと同じ値があっても正しい終端を見つける．
+        let mut buf: Vec<u8> = vec![0x45, 0x22];
+        buf.append(&mut make_packet_with(&mut vec![0x04, 0x80, 0x01, 0x10, 0x04], &framing).unwrap());
+        buf.push(0x32);
+        let (main_data, head, tail) = parser_with(&buf, 0, &framing).unwrap();
+        assert_eq!(main_data, vec![0x04, 0x80, 0x01, 0x10, 0x04]);
+        assert_eq!(head, 2);
+        assert_eq!(tail, buf.len() - 2);
+
+        // メインデータの途中に整合する終端がある（[0x10]のパケットとして読まれる）ので送信できない
+        let data: Vec<u8> = vec![0x10, 0x80, 0x01, 0x10, 0x04, 0x22, 0x33];
+        assert_eq!(make_packet_with(&mut data.clone(), &framing), Err(PacketError::FooterByteInPayload));
+        let mut w = Vec::new();
+        assert!(encode_to_writer(&data, &framing, &mut w).is_err());
+        assert!(w.is_empty());
+        let framing = Framing { terminator: Terminator::None, ..framing };
+        assert_eq!(make_packet_with(&mut vec![0x10, 0x80, 0x01, 0x10, 0x22], &framing), Err(PacketError::FooterByteInPayload));
+    }
+
+    #[test]
+    fn test_parser_lossy() {
+        // チェックサムを2bit反転させる
+        let packet: Vec<u8> = vec![0x00, 0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44 ^ 0x21, 0x04];
+        assert_eq!(parser(&packet, 0), Err(PacketError::ChecksumMismatch { pos: 10 }));
+
+        let (main_data, head, tail, distance) = parser_lossy(&packet, 0).unwrap();
+        assert_eq!(main_data, vec![0x01, 0x23, 0xAB, 0xCD]);
+        assert_eq!(head, 1);
+        assert_eq!(tail, 11);
+        assert_eq!(distance, 2);
+
+        // 正常なパケットの距離は0
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        let (_, _, _, distance) = parser_lossy(&packet, 0).unwrap();
+        assert_eq!(distance, 0);
+    }
+
+    #[test]
+    fn test_length_width_32() {
+        let framing = Framing { length_width: LengthWidth::Bits32, ..Framing::default() };
+        let packet = make_packet_with(&mut vec![0x01, 0x23, 0xAB, 0xCD], &framing).unwrap();
+        assert_eq!(packet, vec![0xA5, 0x5A, 0xA0, 0x00, 0x00, 0x00, 0x04, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04]);
+
+        // 100KBのデータを往復させる
+        let data: Vec<u8> = (0..100_000).map(|n| (n % 251) as u8).collect();
+        for &length_position in &[LengthPosition::Prefix, LengthPosition::Suffix] {
+            for &length_endian in &[Endian::Big, Endian::Little] {
+                let framing = Framing { length_position, length_width: LengthWidth::Bits32, length_endian, ..Framing::default() };
+                let packet = make_packet_with(&mut data.clone(), &framing).unwrap();
+                assert_eq!(packet.len(), data.len() + 9);
+
+                let (main_data, head, tail) = parser_with(&packet, 0, &framing).unwrap();
+                assert_eq!(main_data, data);
+                assert_eq!(head, 0);
+                assert_eq!(tail, packet.len() - 1);
+            }
+        }
+
+        // 15bitの形式では送信できない
+        assert!(make_packet(&mut data.clone()).is_err());
+    }
+
+    #[test]
+    fn test_encode_static() {
+        let ping = encode_static(&[0x50]).unwrap();
+        assert_eq!(ping, &make_packet(&mut vec![0x50]).unwrap()[..]);
+
+        // 2回目以降は同じ領域を返す
+        assert!(std::ptr::eq(ping, encode_static(&[0x50]).unwrap()));
+        assert!(!std::ptr::eq(ping, encode_static(&[0x51]).unwrap()));
+
+        assert!(encode_static(&[]).is_err());
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+
+        // データサイズ部を読む前に途切れている場合は不足分が分からない
+        assert_eq!(parser(&packet[..1], 0), Err(PacketError::HeaderNotFound));
+        assert_eq!(parser(&packet[..2], 0), Err(PacketError::Incomplete { needed: None }));
+        assert_eq!(parser(&packet[..3], 0), Err(PacketError::Incomplete { needed: None }));
+
+        // データサイズ部以降で途切れている場合は不足分を返す
+        for len in 4..packet.len() {
+            assert_eq!(parser(&packet[..len], 0), Err(PacketError::TruncatedBody { declared: 4, available: len, needed: packet.len() - len }));
+            assert_eq!(parser(&packet[..len], 0).unwrap_err().needed(), Some(packet.len() - len));
+        }
+        assert_eq!(parser(&packet[..3], 0).unwrap_err().needed(), None);
+        assert!(parser(&packet, 0).is_ok());
+
+        // 32bitのデータサイズ部
+        let framing = Framing { length_width: LengthWidth::Bits32, ..Framing::default() };
+        let packet = make_packet_with(&mut vec![0x01, 0x23, 0xAB, 0xCD], &framing).unwrap();
+        for len in 2..7 {
+            assert_eq!(parser_with(&packet[..len], 0, &framing), Err(PacketError::Incomplete { needed: None }));
+        }
+        for len in 7..packet.len() {
+            assert_eq!(parser_with(&packet[..len], 0, &framing), Err(PacketError::TruncatedBody { declared: 4, available: len, needed: packet.len() - len }));
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        let framing = Framing { escape: Some(0x10), ..Framing::default() };
+
+        // データサイズ部，メインデータ，チェックサムの全てに0x04が含まれる
+        let data = vec![0x01, 0x02, 0x03, 0x04];
+        assert_eq!(calc_checksum(&data), 0x04);
+
+        let packet = make_packet_with(&mut data.clone(), &framing).unwrap();
+        assert_eq!(packet, vec![0xA5, 0x5A, 0x80, 0x10, 0x24, 0xA0, 0x01, 0x02, 0x03, 0x10, 0x24, 0x10, 0x24, 0x04]);
+        assert_eq!(packet.iter().filter(|&&byte| byte == 0x04).count(), 1);
+
+        let mut buf: Vec<u8> = vec![0x04, 0x45];
+        buf.extend_from_slice(&packet);
+        let (main_data, head, tail) = parser_with(&buf, 0, &framing).unwrap();
+        assert_eq!(main_data, data);
+        assert_eq!(head, 2);
+        assert_eq!(tail, buf.len() - 1);
+
+        // エスケープバイト自身もエスケープする
+        let data = vec![0x10, 0x30];
+        let packet = make_packet_with(&mut data.clone(), &framing).unwrap();
+        assert_eq!(packet, vec![0xA5, 0x5A, 0x80, 0x02, 0xA0, 0x10, 0x30, 0x30, 0x20, 0x04]);
+        assert_eq!(parser_with(&packet, 0, &framing).unwrap().0, data);
+
+        // エスケープの直後でパケットが終わっている
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x01, 0xA0, 0x01, 0x10, 0x04];
+        assert_eq!(parser_with(&packet, 0, &framing), Err(PacketError::InvalidEscape { pos: 6 }));
+
+        // エラーの位置はエスケープされたままのバッファ内の位置
+        let mut packet = make_packet_with(&mut vec![0x04, 0x01], &framing).unwrap();
+        assert_eq!(packet, vec![0xA5, 0x5A, 0x80, 0x02, 0xA0, 0x10, 0x24, 0x01, 0x05, 0x04]);
+        packet[8] ^= 0x02;
+        assert_eq!(parser_with(&packet, 0, &framing), Err(PacketError::ChecksumMismatch { pos: 8 }));
+    }
+
+    #[test]
+    fn test_parse_with_stats() {
+        let mut stats = DecoderStats::default();
+        let packet: Vec<u8> = vec![0x45, 0x22, 0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+
+        assert!(parse_with_stats(&packet, 0, &mut stats).is_ok());
+        assert_eq!(parse_with_stats(&packet, 3, &mut stats), Err(PacketError::HeaderNotFound));
+        assert!(parse_with_stats(&packet[..8], 0, &mut stats).is_err());
+        let mut broken = packet.clone();
+        broken[11] = 0x00;
+        assert!(parse_with_stats(&broken, 0, &mut stats).is_err());
+        broken[6] = 0x00;
+        assert!(parse_with_stats(&broken, 0, &mut stats).is_err());
+
+        assert_eq!(stats, DecoderStats {
+            ok: 1,
+            checksum_error: 1,
+            header_not_found: 1,
+            incomplete: 1,
+            format_error: 1,
+            skipped_bytes: 2 + 10 + 2 * 3,
+        });
+    }
+
+    #[test]
+    fn test_exact_fit() {
+        // フッタがバッファの最後のバイトでも範囲外を読まない
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x01, 0xA0, 0x3C, 0x3C, 0x04];
+        let (main_data, head, tail) = parser(&packet, 0).unwrap();
+        assert_eq!(main_data, vec![0x3C]);
+        assert_eq!(head, 0);
+        assert_eq!(tail, 7);
+
+        let data: Vec<u8> = (0..0x7F).collect();
+        let packet = make_packet(&mut data.clone()).unwrap();
+        let (main_data, head, tail) = parser(&packet, 0).unwrap();
+        assert_eq!(main_data, data);
+        assert_eq!(head, 0);
+        assert_eq!(tail, packet.len() - 1);
+        assert_eq!(packet[tail], 0x04);
+    }
+
+    #[test]
+    fn test_terminator_none() {
+        let framing = Framing { terminator: Terminator::None, ..Framing::default() };
+        let packet = make_packet_with(&mut vec![0x01, 0x23, 0xAB, 0xCD], &framing).unwrap();
+        assert_eq!(packet, vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44]);
+
+        // フッタ無しのパケットが区切り無しで連続している
+        let mut buf = packet.clone();
+        buf.append(&mut make_packet_with(&mut vec![0x10, 0x20], &framing).unwrap());
+        let (main_data, head, tail) = parser_with(&buf, 0, &framing).unwrap();
+        assert_eq!(main_data, vec![0x01, 0x23, 0xAB, 0xCD]);
+        assert_eq!((head, tail), (0, 9));
+        let (main_data, head, tail) = parser_with(&buf, tail + 1, &framing).unwrap();
+        assert_eq!(main_data, vec![0x10, 0x20]);
+        assert_eq!((head, tail), (10, buf.len() - 1));
+
+        assert_eq!(parser_with(&packet[..9], 0, &framing), Err(PacketError::TruncatedBody { declared: 4, available: 9, needed: 1 }));
+
+        // データサイズ部が後ろにある場合
+        let framing = Framing { length_position: LengthPosition::Suffix, ..framing };
+        let mut buf = make_packet_with(&mut vec![0x01, 0x23, 0xAB, 0xCD], &framing).unwrap();
+        buf.append(&mut make_packet_with(&mut vec![0x10, 0x20], &framing).unwrap());
+        let (main_data, _, tail) = parser_with(&buf, 0, &framing).unwrap();
+        assert_eq!(main_data, vec![0x01, 0x23, 0xAB, 0xCD]);
+        assert_eq!(tail, 9);
+        let (main_data, _, tail) = parser_with(&buf, tail + 1, &framing).unwrap();
+        assert_eq!(main_data, vec![0x10, 0x20]);
+        assert_eq!(tail, buf.len() - 1);
+    }
+
+    #[test]
+    fn test_split_payload() {
+        let packet = make_packet(&mut b"12.5,-3,,980".to_vec()).unwrap();
+        let (main_data, _, _) = parser(&packet, 0).unwrap();
+        let fields: Vec<&[u8]> = split_payload(&main_data, b',').collect();
+        assert_eq!(fields, vec![&b"12.5"[..], b"-3", b"", b"980"]);
+    }
+
+    #[test]
+    fn test_error_precedence() {
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        let broken = |pos: &[usize], len: usize| -> Vec<u8> {
+            let mut broken = packet.clone();
+            for &p in pos {
+                broken[p] ^= 0x01;
+            }
+            broken.truncate(len);
+            broken
+        };
+
+        // ヘッダが無ければ他の誤りより優先する
+        assert_eq!(parser(&broken(&[1, 4, 9], 11), 0), Err(PacketError::HeaderNotFound));
+        // 読めたバイトに誤りがあれば，途切れていることより優先する
+        assert_eq!(parser(&[0xA5, 0x5A, 0x00], 0), Err(PacketError::InvalidLengthMarker { pos: 2 }));
+        assert_eq!(parser(&[0xA5, 0x5A, 0x80, 0x00, 0x00], 0), Err(PacketError::NoMainData { pos: 2 }));
+        assert_eq!(parser(&broken(&[4], 7), 0), Err(PacketError::InvalidConstByte { pos: 4 }));
+        // 形式の誤り（同期ずれ）はチェックサムの誤り（ノイズ）より優先する
+        assert_eq!(parser(&broken(&[4, 10], 11), 0), Err(PacketError::InvalidConstByte { pos: 4 }));
+        assert_eq!(parser(&[0xA5, 0x5A, 0x00, 0x04, 0xA1, 0x01, 0x23, 0xAB, 0xCD, 0x45, 0x04], 0), Err(PacketError::InvalidLengthMarker { pos: 2 }));
+        // チェックサムはフッタより前にあるので先に報告する
+        assert_eq!(parser(&broken(&[9, 10], 11), 0), Err(PacketError::ChecksumMismatch { pos: 9 }));
+        assert_eq!(parser(&broken(&[9], 10), 0), Err(PacketError::TruncatedBody { declared: 4, available: 10, needed: 1 }));
+        assert_eq!(parser(&broken(&[10], 11), 0), Err(PacketError::FooterMissing { pos: 10 }));
+
+        // 固定値がデータサイズ部の前にある場合
+        let framing = Framing { length_width: LengthWidth::Bits32, ..Framing::default() };
+        assert_eq!(parser_with(&[0xA5, 0x5A, 0xA1], 0, &framing), Err(PacketError::InvalidConstByte { pos: 2 }));
+        assert_eq!(parser_with(&[0xA5, 0x5A, 0xA0, 0x00, 0x00, 0x00, 0x00, 0x01], 0, &framing), Err(PacketError::NoMainData { pos: 3 }));
+    }
+
+    #[test]
+    fn test_length_offset() {
+        // データサイズ部にチェックサムとフッタの分を含める
+        let framing = Framing { length_offset: 2, ..Framing::default() };
+        let packet = make_packet_with(&mut vec![0x01, 0x23, 0xAB, 0xCD], &framing).unwrap();
+        assert_eq!(packet, vec![0xA5, 0x5A, 0x80, 0x06, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04]);
+        assert_eq!(parser_with(&packet, 0, &framing), Ok((vec![0x01, 0x23, 0xAB, 0xCD], 0, 10)));
+        // declaredはデータサイズ部の値そのもの
+        assert_eq!(parser_with(&packet[..8], 0, &framing), Err(PacketError::TruncatedBody { declared: 6, available: 8, needed: 3 }));
+        // オフセットを知らなければ途切れているように見える
+        assert_eq!(parser(&packet, 0), Err(PacketError::TruncatedBody { declared: 6, available: 11, needed: 2 }));
+
+        let framing = Framing { length_width: LengthWidth::Bits32, length_position: LengthPosition::Suffix, ..framing };
+        let packet = make_packet_with(&mut vec![0x10, 0x20], &framing).unwrap();
+        assert_eq!(parser_with(&packet, 0, &framing).unwrap().0, vec![0x10, 0x20]);
+
+        let framing = Framing { length_offset: -2, ..Framing::default() };
+        assert!(matches!(make_packet_with(&mut vec![0x01, 0x23], &framing), Err(PacketError::InvalidFraming(_))));
+    }
+
+    #[test]
+    fn test_checksum_coverage() {
+        let framing = Framing { checksum_coverage: ChecksumCoverage::LengthAndPayload, ..Framing::default() };
+
+        // 相手の機器から取得したものではなく，XORの計算方法から手で作った合成のテストベクタ．
+        // データサイズ部（0x80, 0x04）も含めたXOR: 0x44 ^ 0x80 ^ 0x04 = 0xC0
+        let frame: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0xC0, 0x04];
+        assert_eq!(make_packet_with(&mut vec![0x01, 0x23, 0xAB, 0xCD], &framing).unwrap(), frame);
+        assert_eq!(parser_with(&frame, 0, &framing).unwrap().0, vec![0x01, 0x23, 0xAB, 0xCD]);
+        assert_eq!(parser(&frame, 0), Err(PacketError::ChecksumMismatch { pos: 9 }));
+
+        for &length_position in &[LengthPosition::Prefix, LengthPosition::Suffix] {
+            for &length_width in &[LengthWidth::Bits15, LengthWidth::Bits32] {
+                let framing = Framing { length_position, length_width, ..framing };
+                let packet = make_packet_with(&mut vec![0x10, 0x20, 0x30], &framing).unwrap();
+                assert_eq!(parser_with(&packet, 0, &framing).unwrap().0, vec![0x10, 0x20, 0x30]);
+                let default = Framing { checksum_coverage: ChecksumCoverage::PayloadOnly, ..framing };
+                assert!(parser_with(&packet, 0, &default).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_length_pair() {
+        // データサイズ部の上位バイトで途切れている
+        assert_eq!(parser(&[0x45, 0xA5, 0x5A, 0x80], 0), Err(PacketError::Incomplete { needed: None }));
+        assert_eq!(parser(&[0xA5, 0x5A, 0x81], 0), Err(PacketError::Incomplete { needed: None }));
+        // データサイズ部の下位バイトで途切れている
+        assert_eq!(parser(&[0x45, 0xA5, 0x5A, 0x80, 0x04], 0), Err(PacketError::TruncatedBody { declared: 4, available: 4, needed: 7 }));
+        // 送信し得ない長さは続きを待たない
+        assert_eq!(parser(&[0xA5, 0x5A, 0x81, 0x00], 0), Err(PacketError::DeclaredExceedsMax { declared: 0x100, max: 0x7F }));
+
+        // 4Byteのデータサイズ部の途中で途切れている
+        let framing = Framing { length_width: LengthWidth::Bits32, length_endian: Endian::Little, ..Framing::default() };
+        let packet = make_packet_with(&mut vec![0x01, 0x23], &framing).unwrap();
+        for len in 3..7 {
+            assert_eq!(parser_with(&packet[..len], 0, &framing), Err(PacketError::Incomplete { needed: None }));
+        }
+        assert_eq!(parser_with(&packet[..7], 0, &framing), Err(PacketError::TruncatedBody { declared: 2, available: 7, needed: 4 }));
+    }
+
+    #[test]
+    fn test_padding() {
+        let framing = Framing { pad_to: Some(16), pad_byte: 0xFF, ..Framing::default() };
+        let packet = make_packet_with(&mut vec![0x01, 0x23], &framing).unwrap();
+        assert_eq!(packet.len(), 16);
+        assert_eq!(&packet[9..], &[0xFF; 7]);
+        let (main_data, _, tail) = parser_with(&packet, 0, &framing).unwrap();
+        assert_eq!(main_data, vec![0x01, 0x23]);
+        assert_eq!(tail, 8);
+
+        // 埋めたバイトは次のヘッダを探すときに読み飛ばされる
+        let mut buf = packet.clone();
+        buf.extend(make_packet_with(&mut vec![0xAB; 7], &framing).unwrap());
+        assert_eq!(buf.len(), 32);
+        let results: Vec<_> = frames(&buf).collect();
+        assert_eq!(results, vec![Ok(vec![0x01, 0x23]), Ok(vec![0xAB; 7])]);
+
+        // パケットがpad_toに収まらない
+        assert_eq!(make_packet_with(&mut vec![0xAB; 10], &framing), Err(PacketError::DataTooLong));
+        // 埋めるバイトがヘッダの先頭と同じ
+        let framing = Framing { pad_byte: 0xA5, ..framing };
+        assert!(matches!(framing.validate(), Err(PacketError::InvalidFraming(_))));
+    }
+
+    #[test]
+    fn test_integrity_only() {
+        let buf = append_integrity(&[0x01, 0x23, 0xAB, 0xCD]);
+        assert_eq!(buf, vec![0x01, 0x23, 0xAB, 0xCD, 0x44]);
+        assert_eq!(verify_and_strip(&buf), Ok(&[0x01, 0x23, 0xAB, 0xCD][..]));
+
+        let mut broken = buf.clone();
+        broken[1] ^= 0x01;
+        assert_eq!(verify_and_strip(&broken), Err(PacketError::ChecksumMismatch { pos: 4 }));
+        assert_eq!(verify_and_strip(&append_integrity(&[])), Ok(&[][..]));
+        assert_eq!(verify_and_strip(&[]), Err(PacketError::Incomplete { needed: Some(1) }));
+    }
+
+    #[test]
+    fn test_drain_garbage() {
+        let packet = make_packet(&mut vec![0x01, 0x23]).unwrap();
+        let mut buf = vec![0x45, 0xA5, 0x22];
+        buf.extend(&packet);
+        assert_eq!(drain_garbage(&buf), 3);
+        assert_eq!(drain_garbage(&buf[3..]), 0);
+        // 中身が壊れていても解析しないのでヘッダの位置を返す
+        assert_eq!(drain_garbage(&buf[3..6]), 0);
+
+        assert_eq!(drain_garbage(&[0x45, 0x22, 0x04]), 3);
+        assert_eq!(drain_garbage(&[0x45, 0x22, 0xA5]), 2);
+        assert_eq!(drain_garbage(&[]), 0);
+    }
+
+    #[test]
+    fn test_patch_payload() {
+        let mut frame = make_packet(&mut vec![0x01, 0x23, 0xAB, 0xCD]).unwrap();
+        patch_payload(&mut frame, 1, &[0x45, 0x67]).unwrap();
+        assert_eq!(frame, make_packet(&mut vec![0x01, 0x45, 0x67, 0xCD]).unwrap());
+
+        // メインデータを超えて書き換えようとした場合は変更しない
+        let before = frame.clone();
+        assert_eq!(patch_payload(&mut frame, 3, &[0x00, 0x00]), Err(PacketError::OffsetOutOfRange));
+        assert_eq!(patch_payload(&mut frame, usize::MAX, &[0x00]), Err(PacketError::OffsetOutOfRange));
+        assert_eq!(patch_payload(&mut frame[1..], 0, &[0x00]), Err(PacketError::HeaderNotFound));
+        assert_eq!(frame, before);
+    }
+
+    #[test]
+    fn test_parse_fixed() {
+        let packet: Vec<u8> = vec![0x45, 0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        assert_eq!(parse_fixed::<4>(&packet), Ok([0x01, 0x23, 0xAB, 0xCD]));
+        assert_eq!(parse_fixed::<2>(&packet), Err(PacketError::LengthMismatch { expected: 2, actual: 4 }));
+        // データサイズ部が一致しなければ，途切れていても先に報告する
+        assert_eq!(parse_fixed::<8>(&packet[..6]), Err(PacketError::LengthMismatch { expected: 8, actual: 4 }));
+        assert_eq!(parse_fixed::<4>(&packet[..6]), Err(PacketError::TruncatedBody { declared: 4, available: 5, needed: 6 }));
+    }
+
+    #[test]
+    fn test_zero_length() {
+        // ノイズの中のメインデータの無いパケット（キープアライブ）
+        let packet: Vec<u8> = vec![0x45, 0xA5, 0x5A, 0x80, 0x00, 0xA0, 0x00, 0x04, 0x11];
+        assert_eq!(parser(&packet, 0), Err(PacketError::ZeroLength { pos: 3 }));
+        // 続きが届いていない場合は判断しない
+        assert_eq!(parser(&packet[..5], 0), Err(PacketError::TruncatedBody { declared: 0, available: 4, needed: 3 }));
+        assert_eq!(parser(&packet[..7], 0), Err(PacketError::TruncatedBody { declared: 0, available: 6, needed: 1 }));
+
+        // データサイズ部が壊れて0になっている（後ろに本来のメインデータが続く）
+        let packet: Vec<u8> = vec![0x45, 0xA5, 0x5A, 0x80, 0x00, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        assert_eq!(parser(&packet, 0), Err(PacketError::NoMainData { pos: 3 }));
+
+        // 固定値がデータサイズ部の前にある場合
+        let framing = Framing { length_width: LengthWidth::Bits32, ..Framing::default() };
+        assert_eq!(parser_with(&[0xA5, 0x5A, 0xA0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04], 0, &framing), Err(PacketError::ZeroLength { pos: 3 }));
+    }
+
+    #[test]
+    fn test_parser_bounded() {
+        let packet: Vec<u8> = vec![0x45, 0x22, 0x01, 0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        assert_eq!(parser_bounded(&packet, 0, 3), parser(&packet, 0));
+        // ヘッダがmax_scanより先にある
+        assert_eq!(parser_bounded(&packet, 0, 2), Err(PacketError::HeaderNotFound));
+        assert_eq!(parser_bounded(&packet, 1, 2).unwrap().1, 3);
+        assert_eq!(parser_bounded(&packet, 3, 0).unwrap().1, 3);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let packet: Vec<u8> = vec![0x45, 0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+        let mut broken = packet.clone();
+        broken[10] ^= 0x01;
+        broken.extend(&packet);
+
+        // デフォルトはparserと同じ
+        let options = ParseOptions::default();
+        assert_eq!(parse_with(&packet, 0, &options), parser(&packet, 0));
+        assert_eq!(parse_with(&broken, 0, &options), parser(&broken, 0));
+
+        let strict = ParseOptions { strict: true, ..options };
+        assert_eq!(parse_with(&packet, 0, &strict), Err(PacketError::HeaderNotFound));
+        assert_eq!(parse_with(&packet, 1, &strict), parser(&packet, 1));
+
+        // 壊れたパケットを読み飛ばす
+        let resync = ParseOptions { resync: true, ..options };
+        assert_eq!(parse_with(&broken, 0, &resync).unwrap().1, 13);
+        assert_eq!(parse_with(&broken[..18], 0, &resync), Err(PacketError::TruncatedBody { declared: 4, available: 5, needed: 6 }));
+        assert_eq!(parse_with(&broken[..12], 0, &resync), Err(PacketError::ChecksumMismatch { pos: 10 }));
+        // max_scanの範囲に正常なパケットが無い
+        let bounded = ParseOptions { max_scan: Some(4), ..resync };
+        assert_eq!(parse_with(&broken, 0, &bounded), Err(PacketError::ChecksumMismatch { pos: 10 }));
+
+        let expected = ParseOptions { expected_len: Some(2), ..options };
+        assert_eq!(parse_with(&packet, 0, &expected), Err(PacketError::LengthMismatch { expected: 2, actual: 4 }));
+        let suffix = ParseOptions { framing: Framing { length_position: LengthPosition::Suffix, ..Framing::default() }, ..expected };
+        let packet = make_packet_with(&mut vec![0x01, 0x23, 0xAB, 0xCD], &suffix.framing).unwrap();
+        assert_eq!(parse_with(&packet, 0, &suffix), Err(PacketError::LengthMismatch { expected: 2, actual: 4 }));
+    }
+
+    #[test]
+    fn test_length_overflow() {
+        // データサイズ部が0xFFFF_FFFF
+        let framing = Framing { length_width: LengthWidth::Bits32, ..Framing::default() };
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0xA0, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        let e = parser_with(&packet, 0, &framing).unwrap_err();
+        if usize::BITS > 32 {
+            assert_eq!(e, PacketError::TruncatedBody { declared: 0xFFFF_FFFF, available: 8, needed: 0xFFFF_FFFF + 1 });
+        } else {
+            assert!(matches!(e, PacketError::DeclaredExceedsMax { declared: 0xFFFF_FFFF, .. }));
+        }
+        let capped = ParseOptions { framing, max_len: Some(64), ..ParseOptions::default() };
+        assert_eq!(parse_with(&packet, 0, &capped), Err(PacketError::DeclaredExceedsMax { declared: 0xFFFF_FFFF, max: 64 }));
+    }
+
+    #[test]
+    fn test_declared_exceeds_max() {
+        // データサイズ部が200Byteを示しているが，受信バッファは64Byteまで
+        let mut packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0xC8, 0xA0];
+        packet.extend([0x11; 16]);
+        let capped = ParseOptions { max_len: Some(64), ..ParseOptions::default() };
+        let e = parse_with(&packet, 0, &capped).unwrap_err();
+        assert_eq!(e, PacketError::DeclaredExceedsMax { declared: 200, max: 64 });
+        assert!(!e.is_retryable());
+        // 指定しなければ送信し得る最大値（0x7F）が上限になり，それより大きな指定では緩めない
+        let e = parse_with(&packet, 0, &ParseOptions::default()).unwrap_err();
+        assert_eq!(e, PacketError::DeclaredExceedsMax { declared: 200, max: 0x7F });
+        let loose = ParseOptions { max_len: Some(1000), ..ParseOptions::default() };
+        assert_eq!(parse_with(&packet, 0, &loose), Err(e));
+        // 4Byteのデータサイズ部では200Byteも送信し得るので続きを待つ
+        let framing = Framing { length_width: LengthWidth::Bits32, ..Framing::default() };
+        let mut long: Vec<u8> = vec![0xA5, 0x5A, 0xA0, 0x00, 0x00, 0x00, 0xC8];
+        long.extend([0x11; 16]);
+        let e = parser_with(&long, 0, &framing).unwrap_err();
+        assert_eq!(e, PacketError::TruncatedBody { declared: 200, available: 23, needed: 186 });
+        assert!(e.is_retryable());
+
+        // 上限以下なら通常通り読む
+        let frame = make_packet(&mut vec![0x22; 64]).unwrap();
+        assert_eq!(parse_with(&frame, 0, &capped).unwrap().0, vec![0x22; 64]);
+        // 上限を超えたパケットは読み飛ばして後ろを探す
+        packet.extend(&frame);
+        let resync = ParseOptions { resync: true, ..capped };
+        assert_eq!(parse_with(&packet, 0, &resync).unwrap().1, 21);
+    }
+
+    #[test]
+    fn test_shared_delimiter() {
+        let framing = Framing { header: &[0x7E], footer: 0x7E, escape: Some(0x7D), ..Framing::default() };
+        assert_eq!(framing.validate(), Ok(()));
+        let first = make_packet_with(&mut vec![0x7E, 0x01], &framing).unwrap();
+        assert_eq!(first, vec![0x7E, 0x80, 0x02, 0xA0, 0x7D, 0x5E, 0x01, 0x7F, 0x7E]);
+        let second = make_packet_with(&mut vec![0x10], &framing).unwrap();
+
+        // 前のパケットのフッタが次のパケットのヘッダを兼ねる．先頭の区切りバイトは重なっていてもよい
+        let mut buf: Vec<u8> = vec![0x7E, 0x7E];
+        buf.extend(&first);
+        buf.extend(&second[1..]);
+        buf.extend(&second);
+        let results: Vec<_> = PacketIter::with_framing(&buf, framing).collect();
+        assert_eq!(results, vec![Ok(vec![0x7E, 0x01]), Ok(vec![0x10]), Ok(vec![0x10])]);
+        assert_eq!(parser_with(&buf, 0, &framing).unwrap().1, 2);
+    }
+
+    #[test]
+    fn test_encode_into_slice() {
+        let mut slab = [0xEEu8; 16];
+        let frame = encode_into_slice(&[0x01, 0x23, 0xAB, 0xCD], &mut slab[2..]).unwrap();
+        assert_eq!(frame, &[0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04]);
+        frame[5] = 0x02;
+        assert_eq!(slab[7], 0x02);
+        assert_eq!(slab[13..], [0xEE; 3]);
+
+        // 収まらない場合は書き込まない
+        let mut slab = [0xEEu8; 10];
+        assert_eq!(encode_into_slice(&[0x01, 0x23, 0xAB, 0xCD], &mut slab), Err(PacketError::DataTooLong));
+        assert_eq!(slab, [0xEE; 10]);
+        assert_eq!(encode_into_slice(&[], &mut slab), Err(PacketError::EmptyData));
+    }
+
+    #[test]
+    fn test_reframe() {
+        let default = Framing::default();
+        let crc = Framing {
+            header: &[0x55, 0xAA],
+            checksum_kind: ChecksumKind::Crc16CcittFalse(Endian::Big),
+            ..Framing::default()
+        };
+        let frame: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD, 0x44, 0x04];
+
+        let converted = reframe(&frame, &default, &crc).unwrap();
+        let mut expected: Vec<u8> = vec![0x55, 0xAA, 0x80, 0x04, 0xA0, 0x01, 0x23, 0xAB, 0xCD];
+        ChecksumKind::Crc16CcittFalse(Endian::Big).write(&[0x01, 0x23, 0xAB, 0xCD], &mut expected);
+        expected.push(0x04);
+        assert_eq!(converted, expected);
+        assert_eq!(parser_with(&converted, 0, &crc).unwrap().0, vec![0x01, 0x23, 0xAB, 0xCD]);
+        assert_eq!(reframe(&converted, &crc, &default).unwrap(), frame);
+
+        // 2Byteのチェックサムの誤り
+        let mut broken = converted.clone();
+        broken[10] ^= 0x01;
+        assert_eq!(reframe(&broken, &crc, &default), Err(PacketError::ChecksumMismatch { pos: 9 }));
+        // データサイズ部が後ろにある形式でも2Byteのチェックサムを使える
+        let suffix = Framing { length_position: LengthPosition::Suffix, ..crc };
+        let converted = reframe(&frame, &default, &suffix).unwrap();
+        assert_eq!(reframe(&converted, &suffix, &default).unwrap(), frame);
+    }
+
+    /// 壊れたパケットや途切れたパケットを解析してもpanicしないことを確認する．
+    #[test]
+    fn test_no_panic() {
+        let framings = [
+            Framing::default(),
+            Framing { length_position: LengthPosition::Suffix, ..Framing::default() },
+            Framing { length_width: LengthWidth::Bits32, ..Framing::default() },
+            Framing { escape: Some(0x10), ..Framing::default() },
+            Framing { terminator: Terminator::None, ..Framing::default() },
+            Framing { checksum_kind: ChecksumKind::Crc16Modbus(Endian::Little), ..Framing::default() },
+            Framing { header: &[0x7E], footer: 0x7E, escape: Some(0x7D), ..Framing::default() },
+        ];
+        for framing in &framings {
+            let packet = make_packet_with(&mut vec![0x04, 0x10, 0x7E, 0xA5, 0x5A], framing).unwrap();
+            let options = ParseOptions { framing: *framing, resync: true, ..ParseOptions::default() };
+            for pos in 0..packet.len() {
+                for &mask in &[0x01, 0x80, 0xFF] {
+                    let mut broken = packet.clone();
+                    broken[pos] ^= mask;
+                    for len in 1..=broken.len() {
+                        let _ = parser_with(&broken[..len], 0, framing);
+                        let _ = parse_with(&broken[..len], 0, &options);
+                        let _ = PacketIter::with_framing(&broken[..len], *framing).count();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_with_framing_guess() {
+        let default = Framing::default();
+        let suffix = Framing { length_position: LengthPosition::Suffix, ..Framing::default() };
+        let crc = Framing { checksum_kind: ChecksumKind::Crc8, ..Framing::default() };
+        let presets = [&default, &suffix, &crc];
+
+        let packet = make_packet_with(&mut vec![0x01, 0x23], &suffix).unwrap();
+        assert_eq!(decode_with_framing_guess(&packet, &presets), Ok((vec![0x01, 0x23], 1)));
+        let packet = make_packet_with(&mut vec![0x01, 0x23], &crc).unwrap();
+        assert_eq!(decode_with_framing_guess(&packet, &presets), Ok((vec![0x01, 0x23], 2)));
+
+        // どの候補でも読めない場合は最初の候補のエラー
+        let packet: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x01, 0xA0, 0x10, 0x11, 0x04];
+        assert_eq!(decode_with_framing_guess(&packet, &presets), Err(PacketError::ChecksumMismatch { pos: 6 }));
+        assert!(matches!(decode_with_framing_guess(&packet, &[]), Err(PacketError::InvalidFraming(_))));
+    }
+
+    #[test]
+    fn test_reject_footer_in_payload() {
+        let framing = Framing { reject_footer_in_payload: true, ..Framing::default() };
+        assert_eq!(make_packet_with(&mut vec![0x01, 0x04], &framing), Err(PacketError::FooterByteInPayload));
+        assert!(make_packet_with(&mut vec![0x01, 0x05], &framing).is_ok());
+        // デフォルトでは検出しない
+        assert!(make_packet(&mut vec![0x01, 0x04]).is_ok());
+        // エスケープする場合はフッタが現れないので問題ない
+        let framing = Framing { escape: Some(0x10), ..framing };
+        assert!(make_packet_with(&mut vec![0x01, 0x04], &framing).is_ok());
+    }
+
+    #[test]
+    fn test_checksum_stage() {
+        let before = Framing { escape: Some(0x10), ..Framing::default() };
+        let after = Framing { checksum_stage: ChecksumStage::AfterEscape, ..before };
+
+        // 対象の機器から取得したものではなく，エスケープとXORの規則から手で作った合成のテストベクタ．
+        // エスケープ後のメインデータ[0x10, 0x24, 0x01]のXOR: 0x35（エスケープ前は0x04 ^ 0x01 = 0x05）
+        let frame: Vec<u8> = vec![0xA5, 0x5A, 0x80, 0x02, 0xA0, 0x10, 0x24, 0x01, 0x35, 0x04];
+        assert_eq!(make_packet_with(&mut vec![0x04, 0x01], &after).unwrap(), frame);
+        assert_eq!(parser_with(&frame, 0, &after).unwrap().0, vec![0x04, 0x01]);
+        assert_eq!(parser_with(&frame, 0, &before), Err(PacketError::ChecksumMismatch { pos: 8 }));
+
+        let frame = make_packet_with(&mut vec![0x04, 0x01], &before).unwrap();
+        assert_eq!(frame[8], 0x05);
+        assert_eq!(parser_with(&frame, 0, &after), Err(PacketError::ChecksumMismatch { pos: 8 }));
+
+        // エスケープしない場合は影響しない
+        let plain = Framing { escape: None, ..after };
+        assert_eq!(make_packet_with(&mut vec![0x04, 0x01], &plain).unwrap(), make_packet(&mut vec![0x04, 0x01]).unwrap());
+    }
+
+    #[test]
+    fn test_encode_to_writer() {
+        let framings = [
+            Framing::default(),
+            Framing { length_position: LengthPosition::Suffix, ..Framing::default() },
+            Framing { length_width: LengthWidth::Bits32, length_endian: Endian::Little, ..Framing::default() },
+            Framing { escape: Some(0x10), checksum_stage: ChecksumStage::AfterEscape, ..Framing::default() },
+            Framing { terminator: Terminator::None, pad_to: Some(16), pad_byte: 0xFF, ..Framing::default() },
+            Framing { checksum_kind: ChecksumKind::Crc16Xmodem(Endian::Big), ..Framing::default() },
+        ];
+        let data = vec![0x04, 0x10, 0x20, 0xA5, 0x5A];
+        for framing in &framings {
+            let mut written = Vec::new();
+            encode_to_writer(&data, framing, &mut written).unwrap();
+            assert_eq!(written, make_packet_with(&mut data.clone(), framing).unwrap());
+        }
+
+        // 生成できない場合は何も書き込まない
+        let mut written = Vec::new();
+        let framing = Framing { pad_to: Some(8), ..Framing::default() };
+        let e = encode_to_writer(&data, &framing, &mut written).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(written.is_empty());
+    }
+
+    /// fixtures/conformance.txtの全てのパケットを生成・解析できることを確認する．
+    #[test]
+    fn test_conformance() {
+        let hex = |text: &str| -> Vec<u8> {
+            text.split_whitespace().map(|token| u8::from_str_radix(token, 16).unwrap()).collect()
+        };
+
+        let mut framing = None;
+        // 節ごとの件数
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for line in include_str!("../fixtures/conformance.txt").lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                framing = Some(match line {
+                    "[default]" => Framing::default(),
+                    "[suffix]" => Framing { length_position: LengthPosition::Suffix, ..Framing::default() },
+                    "[bits32]" => Framing { length_width: LengthWidth::Bits32, ..Framing::default() },
+                    "[escape]" => Framing { escape: Some(0x10), ..Framing::default() },
+                    "[crc16]" => Framing { checksum_kind: ChecksumKind::Crc16CcittFalse(Endian::Big), ..Framing::default() },
+                    _ => panic!("Unknown section: {}", line),
+                });
+                counts.push((line, 0));
+                continue;
+            }
+
+            let framing = framing.as_ref().expect("A fixture appears before any section.");
+            let (data, frame) = line.split_once('|').unwrap();
+            let (data, frame) = (hex(data), hex(frame));
+            assert_eq!(make_packet_with(&mut data.clone(), framing).unwrap(), frame, "{}", line);
+            assert_eq!(parser_with(&frame, 0, framing), Ok((data, 0, frame.len() - 1)), "{}", line);
+            counts.last_mut().unwrap().1 += 1;
+        }
+        assert!(!counts.is_empty());
+        for (section, count) in counts {
+            assert!(count > 0, "No fixtures in {}", section);
+        }
+    }
+
+    #[test]
+    fn test_framing_validate() {
+        assert_eq!(Framing::default().validate(), Ok(()));
+
+        let invalid = [
+            Framing { header: &[], ..Framing::default() },
+            Framing { footer: 0x5A, ..Framing::default() },
+            Framing { escape: Some(0x10), terminator: Terminator::None, ..Framing::default() },
+            Framing { escape: Some(0x04), ..Framing::default() },
+            Framing { escape: Some(0xA5), ..Framing::default() },
+            Framing { escape: Some(0x24), ..Framing::default() },
+            Framing { header: &[0x7E], footer: 0x7E, constant: 0x7E, ..Framing::default() },
+            Framing { header: &[0x7E], footer: 0x7E, constant: 0x7E, length_width: LengthWidth::Bits32, ..Framing::default() },
+        ];
+        for framing in &invalid {
+            assert!(matches!(framing.validate(), Err(PacketError::InvalidFraming(_))), "{:?}", framing);
+            assert_eq!(make_packet_with(&mut vec![0x01], framing).err(), framing.validate().err());
+            assert_eq!(parser_with(&[0xA5, 0x5A, 0x80, 0x01, 0xA0, 0x01, 0x01, 0x04], 0, framing).err(), framing.validate().err());
+        }
+
+        // フッタを使わなければヘッダと同じ値でもよい
+        assert_eq!(Framing { footer: 0x5A, terminator: Terminator::None, ..Framing::default() }.validate(), Ok(()));
+
+        // ヘッダ，固定値，フッタを変更する
+        let framing = Framing { header: &[0x7E], constant: 0x55, footer: 0x0D, escape: Some(0x1B), ..Framing::default() };
+        assert_eq!(framing.validate(), Ok(()));
+        let packet = make_packet_with(&mut vec![0x0D, 0x7E, 0x1B], &framing).unwrap();
+        assert_eq!(packet, vec![0x7E, 0x80, 0x03, 0x55, 0x1B, 0x2D, 0x7E, 0x1B, 0x3B, 0x68, 0x0D]);
+        assert_eq!(parser_with(&packet, 0, &framing).unwrap().0, vec![0x0D, 0x7E, 0x1B]);
+    }
+}
@@ -0,0 +1,127 @@
+// 非同期の読み込み元からパケットを順に取り出すアダプタ（asyncフィーチャ）
+//
+// 非同期ランタイムやfuturesには依存せず，読み込み元はpoll_readと同じ形の関数として受け取る．
+// poll_nextはfutures::Stream::poll_nextと同じ形なので，
+// futures::stream::poll_fn(move |cx| frames.poll_next(cx)) とすればStreamExtの各メソッドで扱える．
+// futures_io::AsyncReadを実装した読み込み元は |cx, buf| Pin::new(&mut reader).poll_read(cx, buf) で渡す．
+
+use std::io;
+use std::task::{Context, Poll};
+
+use crate::{PacketError, StreamParser};
+
+
+/// 1回の読み込みで受け取る最大のByte数
+const CHUNK_SIZE: usize = 256;
+
+/// 非同期に読んだデータからパケットを取り出す．
+/// パケットの区切りを待つ間に読んだデータはStreamParserに溜めておき，次のpoll_nextで続きから読む．
+pub struct FrameStream<R> {
+    read: R,
+    parser: StreamParser,
+    /// 読み込み元の終端に達したか，読み込みに失敗した
+    done: bool,
+    io_error: Option<io::Error>,
+}
+
+impl<R> FrameStream<R>
+where
+    R: FnMut(&mut Context<'_>, &mut [u8]) -> Poll<io::Result<usize>>,
+{
+    /// read: 読み込み元のpoll_read．0を返すと終端とみなす．
+    pub fn new(read: R) -> Self {
+        Self { read, parser: StreamParser::new(), done: false, io_error: None }
+    }
+
+    /// 次のパケットを取り出す．
+    /// 壊れたパケットはStreamParser::next_frameと同様にエラー（位置はストリーム内の位置）を返して続きを読む．
+    /// 読み込み元の終端に達するか読み込みに失敗するとReady(None)を返す．途中で途切れたパケットは捨てる．
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Vec<u8>, PacketError>>> {
+        loop {
+            match self.parser.next_frame() {
+                Ok(Some(main_data)) => return Poll::Ready(Some(Ok(main_data))),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+                Ok(None) => {},
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+
+            let mut chunk = [0; CHUNK_SIZE];
+            match (self.read)(cx, &mut chunk) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(0)) => self.done = true,
+                Poll::Ready(Ok(n)) => self.parser.extend(&chunk[..n]),
+                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => {},
+                Poll::Ready(Err(e)) => {
+                    self.done = true;
+                    self.io_error = Some(e);
+                },
+            }
+        }
+    }
+
+    /// 読み込みに失敗してストリームが終わった場合に，その原因を取り出す．
+    pub fn take_io_error(&mut self) -> Option<io::Error> {
+        self.io_error.take()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::make_packet;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::task::Waker;
+
+    /// 書き込み側と読み込み側を持つパイプ．空の間は読み込み側がPendingを返す．
+    #[derive(Default)]
+    struct Pipe {
+        buf: VecDeque<u8>,
+        closed: bool,
+    }
+
+    fn poll_read(pipe: &RefCell<Pipe>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut pipe = pipe.borrow_mut();
+        if pipe.buf.is_empty() && !pipe.closed {
+            return Poll::Pending;
+        }
+        let n = buf.len().min(pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+            *dst = src;
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    #[test]
+    fn test_frame_stream() {
+        let pipe = Rc::new(RefCell::new(Pipe::default()));
+        let reader = Rc::clone(&pipe);
+        let mut frames = FrameStream::new(move |_: &mut Context<'_>, buf: &mut [u8]| poll_read(&reader, buf));
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let first = make_packet(&mut vec![0x01, 0x23]).unwrap();
+        let mut broken = make_packet(&mut vec![0x10]).unwrap();
+        broken[5] ^= 0x01;
+        let second = make_packet(&mut vec![0xAB, 0xCD]).unwrap();
+
+        // パケットの途中までしか届いていなければ待つ
+        assert_eq!(frames.poll_next(&mut cx), Poll::Pending);
+        pipe.borrow_mut().buf.extend(&first[..4]);
+        assert_eq!(frames.poll_next(&mut cx), Poll::Pending);
+        pipe.borrow_mut().buf.extend(&first[4..]);
+        assert_eq!(frames.poll_next(&mut cx), Poll::Ready(Some(Ok(vec![0x01, 0x23]))));
+
+        // 壊れたパケットを読み飛ばして続きを読み，終端で途切れたパケットは捨てる
+        pipe.borrow_mut().buf.extend(broken.iter().chain(&second).chain(&[0xA5, 0x5A, 0x80]));
+        pipe.borrow_mut().closed = true;
+        assert_eq!(frames.poll_next(&mut cx), Poll::Ready(Some(Err(PacketError::ChecksumMismatch { pos: 15 }))));
+        assert_eq!(frames.poll_next(&mut cx), Poll::Ready(Some(Ok(vec![0xAB, 0xCD]))));
+        assert_eq!(frames.poll_next(&mut cx), Poll::Ready(None));
+        assert_eq!(frames.poll_next(&mut cx), Poll::Ready(None));
+        assert!(frames.take_io_error().is_none());
+    }
+}
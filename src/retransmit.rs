@@ -0,0 +1,101 @@
+// ACKを受け取るまで送信したパケットを保持し，再送が必要なものを返すキュー
+//
+// シーケンス番号（u8）ごとにパケットを1つ保持する．時刻の単位は任意（PacketDecoder::push_timedと同様）．
+
+
+/// ACK待ちのパケット
+#[derive(Debug, Clone)]
+struct Outstanding {
+    seq: u8,
+    frame: Vec<u8>,
+    /// 最後に送信した時刻
+    sent_at: u64,
+}
+
+/// 再送キュー
+#[derive(Debug, Clone)]
+pub struct RetransmitQueue {
+    /// 送信してからこれより長くACKが無ければ再送する
+    timeout: u64,
+    /// 送信した順
+    outstanding: Vec<Outstanding>,
+}
+
+impl RetransmitQueue {
+    pub fn new(timeout: u64) -> Self {
+        Self { timeout, outstanding: Vec::new() }
+    }
+
+    /// パケットを送信したときに呼ぶ．同じ番号のパケットを保持している場合は置き換える．
+    pub fn on_send(&mut self, seq: u8, frame: Vec<u8>, now: u64) {
+        self.outstanding.retain(|entry| entry.seq != seq);
+        self.outstanding.push(Outstanding { seq, frame, sent_at: now });
+    }
+
+    /// ACKを受信したときに呼ぶ．保持していたパケットを捨ててtrueを返す．
+    /// 重複したACKや，送信していない番号のACKの場合は何もせずにfalseを返す．
+    pub fn on_ack(&mut self, seq: u8) -> bool {
+        let len = self.outstanding.len();
+        self.outstanding.retain(|entry| entry.seq != seq);
+        self.outstanding.len() != len
+    }
+
+    /// 送信してからtimeoutより長くACKの無いパケットを，送信した順に返す．
+    /// 返したパケットはnowに再送したものとして扱い，次は再びtimeout後に返す．
+    pub fn due_for_retransmit(&mut self, now: u64) -> Vec<&[u8]> {
+        let timeout = self.timeout;
+        self.outstanding.iter_mut()
+            .filter(|entry| now.saturating_sub(entry.sent_at) > timeout)
+            .map(|entry| {
+                entry.sent_at = now;
+                &entry.frame[..]
+            })
+            .collect()
+    }
+
+    /// ACK待ちのパケットの数
+    pub fn len(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// ACK待ちのパケットが無ければtrue
+    pub fn is_empty(&self) -> bool {
+        self.outstanding.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::make_packet;
+
+    #[test]
+    fn test_retransmit() {
+        let frames: Vec<Vec<u8>> = (0..3).map(|n| make_packet(&mut vec![n]).unwrap()).collect();
+        let mut queue = RetransmitQueue::new(10);
+        // 255の次は0
+        queue.on_send(255, frames[0].clone(), 0);
+        queue.on_send(0, frames[1].clone(), 5);
+        queue.on_send(1, frames[2].clone(), 5);
+        assert_eq!(queue.len(), 3);
+        assert!(queue.due_for_retransmit(10).is_empty());
+
+        // ACK（重複したACKは無視する）
+        assert!(queue.on_ack(0));
+        assert!(!queue.on_ack(0));
+        assert!(!queue.on_ack(2));
+        assert_eq!(queue.len(), 2);
+
+        // タイムアウトしたものを古い順に返し，再送した時刻から数え直す
+        assert_eq!(queue.due_for_retransmit(11), vec![&frames[0][..]]);
+        assert_eq!(queue.due_for_retransmit(16), vec![&frames[2][..]]);
+        assert!(queue.due_for_retransmit(20).is_empty());
+        assert_eq!(queue.due_for_retransmit(30), vec![&frames[0][..], &frames[2][..]]);
+
+        assert!(queue.on_ack(255));
+        assert!(queue.on_ack(1));
+        assert!(queue.is_empty());
+        assert!(queue.due_for_retransmit(100).is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+// シーケンス番号（u8，255の次は0）の扱い
+
+use std::cmp::Ordering;
+
+
+/// シーケンス番号を比較する．255の次は0として扱う．
+/// aがbより後（差が1〜127）ならGreater，先（差が128〜255）ならLessを返す．
+/// 差がちょうど128の場合はどちらが先か決まらないので，その範囲の番号を比較しないこと．
+pub fn seq_cmp(a: u8, b: u8) -> Ordering {
+    (a.wrapping_sub(b) as i8).cmp(&0)
+}
+
+/// 順番が入れ替わって届いたパケットをシーケンス番号順に並べ替える．
+/// window: 一度に並べ替える範囲の番号の幅（1〜128）．
+///         全ての番号が，最も古い番号からwindow未満の範囲に収まっている必要がある．
+/// seq   : 要素からシーケンス番号を取り出す関数
+/// 同じ番号の要素は元の順番を保つ．
+pub fn reorder<T, F>(frames: &mut [T], window: u8, seq: F)
+where F: Fn(&T) -> u8 {
+    assert!((1..=128).contains(&window), "window must be in 1..=128");
+    let base = match frames.first() {
+        Some(frame) => seq(frame),
+        None => return,
+    };
+    // 先頭の番号からwindow - 1だけ前を0とした位置で並べる
+    let origin = base.wrapping_sub(window - 1);
+    frames.sort_by_key(|frame| seq(frame).wrapping_sub(origin));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seq_cmp() {
+        assert_eq!(seq_cmp(1, 0), Ordering::Greater);
+        assert_eq!(seq_cmp(0, 255), Ordering::Greater);
+        assert_eq!(seq_cmp(255, 0), Ordering::Less);
+        assert_eq!(seq_cmp(10, 200), Ordering::Greater);
+        assert_eq!(seq_cmp(7, 7), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_reorder_wraparound() {
+        let mut frames: Vec<(u8, &str)> = vec![(0, "c"), (254, "a"), (1, "d"), (255, "b")];
+        reorder(&mut frames, 4, |frame| frame.0);
+        assert_eq!(frames, vec![(254, "a"), (255, "b"), (0, "c"), (1, "d")]);
+
+        // 先頭が最も新しい番号でも並べ替えられる
+        let mut seqs: Vec<u8> = vec![2, 253, 0, 255, 1, 254];
+        reorder(&mut seqs, 8, |&s| s);
+        assert_eq!(seqs, vec![253, 254, 255, 0, 1, 2]);
+
+        let mut seqs: Vec<u8> = vec![];
+        reorder(&mut seqs, 8, |&s| s);
+    }
+}
@@ -0,0 +1,20 @@
+// 受信状況の統計
+
+
+/// パケットの解析結果を集計する．
+/// parse_with_stats等に渡すと，呼び出す度に該当するカウンタが増える．
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecoderStats {
+    /// 正常に読めたパケットの数
+    pub ok: u64,
+    /// チェックサムが一致しなかったパケットの数
+    pub checksum_error: u64,
+    /// ヘッダが見つからなかった回数
+    pub header_not_found: u64,
+    /// パケットが途切れていた回数
+    pub incomplete: u64,
+    /// それ以外の形式の誤り（固定値，フッタ等）の数
+    pub format_error: u64,
+    /// ヘッダを探す際に読み飛ばしたByte数
+    pub skipped_bytes: u64,
+}
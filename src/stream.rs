@@ -0,0 +1,209 @@
+// 受信したデータを溜めながらパケットを取り出す，ストリーム内の位置を数えるパーサ
+//
+// 読み終えたデータはバッファから捨てるが，捨てたByte数をbaseとして数えておき，
+// エラーの位置をストリームの先頭からの位置にして返す．
+// 受信バッファをVecDequeで持っている場合はparse_frontで先頭から取り出せる．
+// バッファを自分で持ったまま続きから読む場合はParseCursorを使う．
+
+use std::collections::VecDeque;
+
+use crate::{find_header, parser, Framing, PacketError};
+
+
+/// ストリーム内の位置を数えるパーサ
+#[derive(Debug, Clone, Default)]
+pub struct StreamParser {
+    buf: Vec<u8>,
+    base: usize,
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Self { buf: Vec::new(), base: 0 }
+    }
+
+    /// 受信したバイト列を追加する．
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// バッファの先頭のストリーム内の位置（これまでに捨てたByte数）
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// 次のパケットを取り出す．
+    /// パケットが揃っていない場合はOk(None)を返し，続きを追加すれば次回の呼び出しで読む．
+    /// 壊れたパケットの場合は，エラーの位置をストリーム内の位置にして返し，
+    /// 次回はそのヘッダの次のバイトから探索を続ける．
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, PacketError> {
+        let (consumed, result) = next_in(&self.buf);
+        let result = result.map_err(|e| e.with_base(self.base));
+        self.consume(consumed);
+        result
+    }
+
+    /// バッファの先頭からnByteを捨てる．
+    fn consume(&mut self, n: usize) {
+        self.buf.drain(..n);
+        self.base += n;
+    }
+}
+
+/// 伸びていくバッファを繰り返し解析するためのカーソル
+/// 次に探索を始める位置だけを持ち，呼び出すたびにバッファの先頭からではなく続きから読む．
+/// バッファには呼び出しの間にデータを追加してよい（先頭を削除した場合はresetする）．
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParseCursor {
+    pos: usize,
+}
+
+impl ParseCursor {
+    pub fn new() -> Self {
+        Self { pos: 0 }
+    }
+
+    /// 次に探索を始める位置
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// バッファの先頭から探索し直す．
+    pub fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    /// bufの続きから次のパケットを読む．
+    /// パケットが揃っていない場合は，そのヘッダの位置から先に進まずにOk(None)を返す．
+    /// 壊れたパケットの場合は，そのヘッダの次から探索を続ける．
+    pub fn next(&mut self, buf: &[u8]) -> Result<Option<Vec<u8>>, PacketError> {
+        let (consumed, result) = if self.pos < buf.len() { next_in(&buf[self.pos..]) } else { (0, Ok(None)) };
+        self.pos += consumed;
+        result.map_err(|e| e.with_base(self.pos - consumed))
+    }
+}
+
+/// VecDequeの受信バッファの先頭からパケットを取り出す．
+/// 完成したパケットがあれば，その終端まで（前にあるゴミを含む）を取り除いてメインデータを返す．
+/// 揃っていない場合はOk(None)を返す．壊れたパケットの場合は，そのヘッダまでを取り除いてエラーを返す．
+/// エラーの位置は呼び出し時のバッファの先頭からの位置．
+pub fn parse_front(buf: &mut VecDeque<u8>) -> Result<Option<Vec<u8>>, PacketError> {
+    // リングバッファの境界をまたいでいる場合は詰め直す
+    let (consumed, result) = next_in(buf.make_contiguous());
+    buf.drain(..consumed);
+    result
+}
+
+/// バッファの先頭から次のパケットを読む．
+/// return: (バッファの先頭から捨てるByte数, 結果)
+fn next_in(buf: &[u8]) -> (usize, Result<Option<Vec<u8>>, PacketError>) {
+    if buf.is_empty() {
+        return (0, Ok(None));
+    }
+
+    match parser(buf, 0) {
+        Ok((main_data, _, tail_pos)) => (tail_pos + 1, Ok(Some(main_data))),
+        Err(PacketError::HeaderNotFound) => {
+            // ヘッダの途中で途切れている可能性があるので，その分は残す
+            let keep = Framing::default().header.len() - 1;
+            (buf.len().saturating_sub(keep), Ok(None))
+        },
+        Err(e) if e.is_retryable() => {
+            // ヘッダより前のデータは不要
+            (find_header(buf, 0, Framing::default().header).unwrap(), Ok(None))
+        },
+        Err(e) => {
+            // 次はヘッダの次のバイトから探索する
+            (find_header(buf, 0, Framing::default().header).unwrap() + 1, Err(e))
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::make_packet;
+
+    #[test]
+    fn test_stream_position() {
+        let mut broken = make_packet(&mut vec![0x10]).unwrap();
+        broken[5] ^= 0x01;
+
+        let mut stream = StreamParser::new();
+        stream.extend(&[0x45, 0x22]);
+        stream.extend(&make_packet(&mut vec![0x01, 0x23]).unwrap());
+        stream.extend(&broken[..3]);
+        assert_eq!(stream.next_frame(), Ok(Some(vec![0x01, 0x23])));
+        assert_eq!(stream.next_frame(), Ok(None));
+        assert_eq!(stream.base(), 11);
+
+        // バッファ内では6Byte目だが，ストリームの先頭からは17Byte目
+        stream.extend(&broken[3..]);
+        assert_eq!(parser(&broken, 0), Err(PacketError::ChecksumMismatch { pos: 6 }));
+        assert_eq!(stream.next_frame(), Err(PacketError::ChecksumMismatch { pos: 17 }));
+        assert_eq!(stream.next_frame(), Ok(None));
+
+        // ヘッダの途中で途切れていても続きを読める
+        let packet = make_packet(&mut vec![0xAB]).unwrap();
+        stream.extend(&packet[..1]);
+        assert_eq!(stream.next_frame(), Ok(None));
+        stream.extend(&packet[1..]);
+        assert_eq!(stream.next_frame(), Ok(Some(vec![0xAB])));
+        assert_eq!(stream.base(), 27);
+    }
+
+    #[test]
+    fn test_parse_cursor() {
+        let packet = make_packet(&mut vec![0x01, 0x23]).unwrap();
+        let mut buf: Vec<u8> = vec![0x45];
+        let mut cursor = ParseCursor::new();
+
+        // 途切れたパケットの手前までは進む
+        buf.extend(&packet[..4]);
+        assert_eq!(cursor.next(&buf), Ok(None));
+        assert_eq!(cursor.position(), 1);
+        buf.extend(&packet[4..]);
+        assert_eq!(cursor.next(&buf), Ok(Some(vec![0x01, 0x23])));
+        assert_eq!(cursor.position(), buf.len());
+        assert_eq!(cursor.next(&buf), Ok(None));
+
+        // エラーの位置はバッファ内の位置
+        let mut broken = make_packet(&mut vec![0x10]).unwrap();
+        broken[5] ^= 0x01;
+        buf.extend(&broken);
+        buf.extend(&packet[..1]);
+        assert_eq!(cursor.next(&buf), Err(PacketError::ChecksumMismatch { pos: 16 }));
+        assert_eq!(cursor.next(&buf), Ok(None));
+        buf.extend(&packet[1..]);
+        assert_eq!(cursor.next(&buf), Ok(Some(vec![0x01, 0x23])));
+    }
+
+    #[test]
+    fn test_parse_front() {
+        let mut stream: Vec<u8> = vec![0x45, 0x22];
+        stream.extend(make_packet(&mut vec![0x01, 0x23]).unwrap());
+        stream.extend(make_packet(&mut vec![0xAB; 5]).unwrap());
+        stream.extend(make_packet(&mut vec![0x10]).unwrap());
+
+        // 先頭を取り出しながら追加するので，データがリングバッファの境界をまたぐ
+        let mut buf: VecDeque<u8> = VecDeque::with_capacity(8);
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(3) {
+            buf.extend(chunk);
+            while let Some(main_data) = parse_front(&mut buf).unwrap() {
+                frames.push(main_data);
+            }
+        }
+        assert_eq!(frames, vec![vec![0x01, 0x23], vec![0xAB; 5], vec![0x10]]);
+        assert!(buf.is_empty());
+
+        // 壊れたパケットはヘッダまで取り除く
+        let mut broken = make_packet(&mut vec![0x10]).unwrap();
+        broken[5] ^= 0x01;
+        let mut buf: VecDeque<u8> = broken.into_iter().collect();
+        assert_eq!(parse_front(&mut buf), Err(PacketError::ChecksumMismatch { pos: 6 }));
+        assert_eq!(buf.len(), 7);
+        assert_eq!(parse_front(&mut buf), Ok(None));
+    }
+}
@@ -0,0 +1,417 @@
+// パケットの送受信路を抽象化するトレイトと，その実装
+//
+// アプリケーションをTransportに対して書いておけば，シリアルポート，
+// メモリ上のループバック，ファイルの再生を入れ替えても同じコードで動く．
+// UDPで送受信する場合は，データグラム1つに1つのパケットを入れる（send_to_framed，recv_framed）．
+// パケットの組み立てと解析はどの実装でも共通（make_packetとPacketDecoder）．
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+
+use crate::{make_packet, parser, parser_with, Framing, PacketDecoder, PacketError, Terminator};
+
+
+/// パケット単位の送受信路
+pub trait Transport {
+    /// メインデータをパケットにして送信する．
+    fn send_frame(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// パケットを1つ受信し，メインデータを返す．
+    /// 壊れたパケットは読み飛ばす．
+    fn recv_frame(&mut self) -> io::Result<Vec<u8>>;
+}
+
+/// 読み出したバイト列からパケットを復元する．
+/// 1回のreadで複数のパケットを読んだ場合に備えて，未処理のバイトを保持する．
+#[derive(Debug, Clone, Default)]
+struct Receiver {
+    decoder: PacketDecoder,
+    pending: VecDeque<u8>,
+}
+
+impl Receiver {
+    fn recv<R: Read>(&mut self, reader: &mut R) -> io::Result<Vec<u8>> {
+        let mut buf = [0u8; 256];
+        loop {
+            while let Some(byte) = self.pending.pop_front() {
+                if let Some(main_data) = self.decoder.push(byte) {
+                    return Ok(main_data);
+                }
+            }
+
+            let n = match reader.read(&mut buf) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Stream ended before a complete packet."));
+            }
+            self.pending.extend(&buf[..n]);
+        }
+    }
+}
+
+fn encode(data: &[u8]) -> io::Result<Vec<u8>> {
+    make_packet(&mut data.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Read + Writeを実装する送受信路（シリアルポート等）
+#[derive(Debug)]
+pub struct StreamTransport<T> {
+    inner: T,
+    receiver: Receiver,
+}
+
+impl<T: Read + Write> StreamTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            receiver: Receiver::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read + Write> Transport for StreamTransport<T> {
+    fn send_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        let packet = encode(data)?;
+        self.inner.write_all(&packet)
+    }
+
+    fn recv_frame(&mut self) -> io::Result<Vec<u8>> {
+        self.receiver.recv(&mut self.inner)
+    }
+}
+
+/// 送信したパケットをそのまま受信するメモリ上の送受信路（テスト用）．
+/// 受信するパケットが無ければUnexpectedEofを返す．
+pub type LoopbackTransport = StreamTransport<VecDeque<u8>>;
+
+impl LoopbackTransport {
+    pub fn loopback() -> Self {
+        Self::new(VecDeque::new())
+    }
+}
+
+/// 記録した受信データ（生のバイト列）をファイルから再生する送受信路．
+/// 送信したメインデータは相手がいないので，sent()で確認できるように保持するだけ．
+#[derive(Debug)]
+pub struct FileReplayTransport {
+    file: BufReader<File>,
+    receiver: Receiver,
+    sent: Vec<Vec<u8>>,
+}
+
+impl FileReplayTransport {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            file: BufReader::new(File::open(path)?),
+            receiver: Receiver::default(),
+            sent: Vec::new(),
+        })
+    }
+
+    /// これまでに送信したメインデータ
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+}
+
+impl Transport for FileReplayTransport {
+    fn send_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        encode(data)?;
+        self.sent.push(data.to_vec());
+        Ok(())
+    }
+
+    fn recv_frame(&mut self) -> io::Result<Vec<u8>> {
+        self.receiver.recv(&mut self.file)
+    }
+}
+
+
+// ------------------------------ 記録形式 -----------------------------------
+// [direction, len(4Byte, big endian), bytes...] の繰り返し
+// direction: 0x00 = 送信，0x01 = 受信
+// bytes    : その時に送受信した生のバイト列（パケットそのもの）
+// -----------------------------------------------------------------------------
+const DIR_SENT: u8 = 0x00;
+const DIR_RECEIVED: u8 = 0x01;
+
+fn write_record<W: Write>(log: &mut W, direction: u8, bytes: &[u8]) -> io::Result<()> {
+    log.write_all(&[direction])?;
+    log.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    log.write_all(bytes)
+}
+
+/// 送受信した全ての生のバイト列をlogに記録する送受信路．
+/// 壊れたパケットやゴミも含めて記録できるように，Transportではなく
+/// その下のバイト列（Read + Write）を包む．
+/// 記録したログはReplayTransportで再生できる．
+#[derive(Debug)]
+pub struct RecordingTransport<T, W> {
+    inner: T,
+    log: W,
+    receiver: Receiver,
+}
+
+impl<T: Read + Write, W: Write> RecordingTransport<T, W> {
+    pub fn new(inner: T, log: W) -> Self {
+        Self {
+            inner,
+            log,
+            receiver: Receiver::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> (T, W) {
+        (self.inner, self.log)
+    }
+}
+
+/// 読み出したバイト列をそのままlogに記録するReader
+struct Tee<'a, T, W> {
+    inner: &'a mut T,
+    log: &'a mut W,
+}
+
+impl<'a, T: Read, W: Write> Read for Tee<'a, T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            write_record(self.log, DIR_RECEIVED, &buf[..n])?;
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Read + Write, W: Write> Transport for RecordingTransport<T, W> {
+    fn send_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        let packet = encode(data)?;
+        self.inner.write_all(&packet)?;
+        write_record(&mut self.log, DIR_SENT, &packet)
+    }
+
+    fn recv_frame(&mut self) -> io::Result<Vec<u8>> {
+        let mut tee = Tee { inner: &mut self.inner, log: &mut self.log };
+        self.receiver.recv(&mut tee)
+    }
+}
+
+/// RecordingTransportで記録したログを再生する送受信路．
+/// 受信側の記録をPacketDecoderに通すので，記録時と同じパケットを復元できる．
+/// 送信したメインデータはsent()で確認できる．
+#[derive(Debug)]
+pub struct ReplayTransport<R> {
+    log: R,
+    receiver: Receiver,
+    received: VecDeque<u8>,
+    sent: Vec<Vec<u8>>,
+}
+
+impl<R: Read> ReplayTransport<R> {
+    pub fn new(log: R) -> Self {
+        Self {
+            log,
+            receiver: Receiver::default(),
+            received: VecDeque::new(),
+            sent: Vec::new(),
+        }
+    }
+
+    /// これまでに送信したメインデータ
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+
+    /// 受信側の記録を1つ読み出してreceivedに追加する．
+    /// 記録の終端に達した場合はfalseを返す．
+    fn read_record(&mut self) -> io::Result<bool> {
+        loop {
+            let mut head = [0u8; 5];
+            match self.log.read_exact(&mut head) {
+                Ok(()) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            let len = u32::from_be_bytes([head[1], head[2], head[3], head[4]]) as usize;
+            let mut bytes = vec![0u8; len];
+            self.log.read_exact(&mut bytes)?;
+
+            match head[0] {
+                DIR_SENT => continue,
+                DIR_RECEIVED => {
+                    self.received.extend(bytes);
+                    return Ok(true);
+                },
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown record direction.")),
+            }
+        }
+    }
+}
+
+impl<R: Read> Transport for ReplayTransport<R> {
+    fn send_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        encode(data)?;
+        self.sent.push(data.to_vec());
+        Ok(())
+    }
+
+    fn recv_frame(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            match self.receiver.recv(&mut self.received) {
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    if !self.read_record()? {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Replay log ended."));
+                    }
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+/// メインデータをパケットにしてUDPのデータグラム1つで送信する．
+pub fn send_to_framed<A: ToSocketAddrs>(socket: &UdpSocket, addr: A, data: &[u8]) -> io::Result<()> {
+    let packet = encode(data)?;
+    socket.send_to(&packet, addr)?;
+    Ok(())
+}
+
+/// UDPのデータグラムを1つ受信し，メインデータと送信元を返す．
+/// データグラム1つにちょうど1つのパケットが入っているものとする．
+/// データグラムで区切られているので，フッタは無くてもよい．
+/// パケットの前後に余分なバイトがある場合や，パケットが壊れている・途切れている場合はInvalidDataを返す．
+pub fn recv_framed(socket: &UdpSocket) -> io::Result<(Vec<u8>, SocketAddr)> {
+    let mut buf = [0u8; 0xFFFF];
+    let (n, addr) = socket.recv_from(&mut buf)?;
+    let datagram = &buf[..n];
+
+    let invalid = |e: PacketError| io::Error::new(io::ErrorKind::InvalidData, e);
+    let whole = |(main_data, head_pos, tail_pos): (Vec<u8>, usize, usize)| {
+        if head_pos == 0 && tail_pos == datagram.len() - 1 {
+            Ok((main_data, addr))
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "The datagram contains bytes outside the packet."))
+        }
+    };
+    match parser(datagram, 0) {
+        Ok(result) => whole(result),
+        // フッタを省略したパケットとして読み直す
+        Err(e) => {
+            let framing = Framing { terminator: Terminator::None, ..Framing::default() };
+            match parser_with(datagram, 0, &framing) {
+                Ok(result) => whole(result),
+                Err(_) => Err(invalid(e)),
+            }
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 送受信路の実装によらないアプリケーション側の処理
+    fn echo<T: Transport>(transport: &mut T) -> io::Result<Vec<u8>> {
+        let request = transport.recv_frame()?;
+        transport.send_frame(&request)?;
+        Ok(request)
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut transport = LoopbackTransport::loopback();
+        transport.send_frame(&[0x01, 0x23]).unwrap();
+        transport.send_frame(&[0xAB, 0xCD]).unwrap();
+
+        assert_eq!(echo(&mut transport).unwrap(), vec![0x01, 0x23]);
+        assert_eq!(transport.recv_frame().unwrap(), vec![0xAB, 0xCD]);
+        assert_eq!(transport.recv_frame().unwrap(), vec![0x01, 0x23]);
+        assert_eq!(transport.recv_frame().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        assert_eq!(transport.send_frame(&[]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_file_replay() {
+        let path = std::env::temp_dir().join(format!("serial-packet-replay-{}.bin", std::process::id()));
+        let mut capture: Vec<u8> = vec![0x45, 0x22];
+        capture.extend(make_packet(&mut vec![0x01, 0x23, 0xAB, 0xCD]).unwrap());
+        capture.push(0x32);
+        capture.extend(make_packet(&mut vec![0x10]).unwrap());
+        std::fs::write(&path, &capture).unwrap();
+
+        let mut transport = FileReplayTransport::open(&path).unwrap();
+        assert_eq!(echo(&mut transport).unwrap(), vec![0x01, 0x23, 0xAB, 0xCD]);
+        assert_eq!(transport.recv_frame().unwrap(), vec![0x10]);
+        assert!(transport.recv_frame().is_err());
+        assert_eq!(transport.sent(), &[vec![0x01, 0x23, 0xAB, 0xCD]]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        // 受信データにはゴミと壊れたパケットが混ざっている
+        let mut wire: VecDeque<u8> = VecDeque::new();
+        wire.extend(&[0x45, 0xA5, 0x22]);
+        wire.extend(make_packet(&mut vec![0x01, 0x23]).unwrap());
+        let mut broken = make_packet(&mut vec![0x04, 0xA5, 0x5A]).unwrap();
+        broken[6] ^= 0x01;
+        wire.extend(broken);
+        wire.extend(make_packet(&mut vec![0x04, 0xA5, 0x5A]).unwrap());
+
+        let mut recorder = RecordingTransport::new(wire.clone(), Vec::new());
+        let seen = vec![echo(&mut recorder).unwrap(), recorder.recv_frame().unwrap()];
+        assert_eq!(seen, vec![vec![0x01, 0x23], vec![0x04, 0xA5, 0x5A]]);
+        let (_, log) = recorder.into_inner();
+
+        // 生のバイト列がそのまま記録されている
+        assert_eq!(log[0], DIR_RECEIVED);
+        assert_eq!(&log[5..8], &[0x45, 0xA5, 0x22]);
+
+        let mut replay = ReplayTransport::new(&log[..]);
+        for main_data in &seen {
+            assert_eq!(&replay.recv_frame().unwrap(), main_data);
+        }
+        assert_eq!(replay.recv_frame().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+
+        send_to_framed(&sender, addr, &[0x01, 0x23]).unwrap();
+        assert_eq!(recv_framed(&receiver).unwrap(), (vec![0x01, 0x23], sender.local_addr().unwrap()));
+
+        // フッタを省略したパケット
+        let packet = make_packet(&mut vec![0xAB, 0xCD]).unwrap();
+        sender.send_to(&packet[..(packet.len() - 1)], addr).unwrap();
+        assert_eq!(recv_framed(&receiver).unwrap().0, vec![0xAB, 0xCD]);
+
+        // 前にゴミがある，途切れている
+        let mut garbage = vec![0x45];
+        garbage.extend(&packet);
+        sender.send_to(&garbage, addr).unwrap();
+        assert_eq!(recv_framed(&receiver).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        sender.send_to(&packet[..5], addr).unwrap();
+        let e = recv_framed(&receiver).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.into_inner().unwrap().downcast_ref::<PacketError>(), Some(&PacketError::TruncatedBody { declared: 2, available: 5, needed: 4 }));
+    }
+}